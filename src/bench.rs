@@ -0,0 +1,416 @@
+use crate::draw_utils::{make_tha_maze, GenerationType, MazeType, LEVELS};
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition};
+use crate::mask::Mask;
+use crate::polar::PolarOptions;
+use crate::solve;
+use crate::stats::{self, MazeStats};
+use crate::{cube, delta, grid3d, hexagonal, polar, rectangle, sphere, upsilon, weave};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
+
+pub const ALL_MAZE_TYPES: [MazeType; 9] = [
+    MazeType::Regular,
+    MazeType::Circular,
+    MazeType::Hexagonal,
+    MazeType::Delta,
+    MazeType::Weave,
+    MazeType::ThreeD,
+    MazeType::Cube,
+    MazeType::Sphere,
+    MazeType::Upsilon,
+];
+
+pub const ALL_GENERATION_TYPES: [GenerationType; 7] = [
+    GenerationType::RecursiveBacktracker,
+    GenerationType::AldousBroder,
+    GenerationType::SimplifiedPrim,
+    GenerationType::TruePrim,
+    GenerationType::Kruskal,
+    GenerationType::BinaryTree,
+    GenerationType::Sidewinder,
+];
+
+/// Bars of the histograms.
+const HISTOGRAM_BUCKETS: usize = 8;
+const HISTOGRAM_WIDTH: usize = 40;
+
+/// Heatmap arrows for the lean of a cell, clockwise from east with y down.
+const ARROWS: [char; 8] = ['→', '↘', '↓', '↙', '←', '↖', '↑', '↗'];
+/// Leans shorter than this count as none in the heatmap; with few runs, leans within twice
+/// their noise do too.
+const MIN_BIAS: f64 = 0.3;
+
+pub struct BenchOptions {
+    pub runs: usize,
+    pub maze_types: Vec<MazeType>,
+    pub generation_types: Vec<GenerationType>,
+    pub rows: usize,
+    pub cols: usize,
+    pub levels: usize,
+    pub braid_chance: u8,
+}
+
+impl Default for BenchOptions {
+    fn default() -> BenchOptions {
+        BenchOptions {
+            runs: 10,
+            maze_types: ALL_MAZE_TYPES.to_vec(),
+            generation_types: ALL_GENERATION_TYPES.to_vec(),
+            rows: 20,
+            cols: 20,
+            levels: LEVELS,
+            braid_chance: 0,
+        }
+    }
+}
+
+/// What the runs of one generator on one maze type produced.
+struct Samples {
+    times: Vec<Duration>,
+    stats: Vec<MazeStats>,
+    /// Per cell, the sum over runs of `parent_steps`.
+    steps: Vec<(f64, f64)>,
+    /// Row and column of each cell, when those tell the cells apart.
+    layout: Option<Vec<(usize, usize)>>,
+}
+
+/// Unit vector from every cell to the one before it on its shortest way to the first active
+/// cell, in the cell positions' coordinates; zero for that cell and the ones it can not
+/// reach. Passages go both ways, so their own directions always average out, but these
+/// steps show how the generator shaped the tree.
+fn parent_steps<C: AbstractCell, T: AbstractGrid<C> + CellPosition>(g: &T) -> Vec<(f64, f64)> {
+    let Some(root) = (0..g.len()).find(|&ix| g.is_active(ix)) else {
+        return vec![(0., 0.); g.len()];
+    };
+    let distance = solve::distances(g, &[root]);
+    (0..g.len())
+        .map(|ix| {
+            let Some(d) = distance[ix].filter(|&d| d > 0) else {
+                return (0., 0.);
+            };
+            // braided mazes can have several, take one that does not depend on hashing
+            let parent = g
+                .links(ix)
+                .into_iter()
+                .filter(|&n| distance[n] == Some(d - 1))
+                .min()
+                .unwrap();
+            let ((x, y), (px, py)) = (g.position(ix), g.position(parent));
+            let length = (px - x).hypot(py - y);
+            if length > 0. {
+                ((px - x) / length, (py - y) / length)
+            } else {
+                (0., 0.)
+            }
+        })
+        .collect()
+}
+
+/// Generates `runs` mazes on grids from `make`, timing only the generator.
+fn sample<C: AbstractCell, T: AbstractGrid<C> + CellPosition>(
+    make: impl Fn() -> T,
+    generation_type: &GenerationType,
+    opts: &BenchOptions,
+) -> Samples {
+    let mut samples = Samples {
+        times: Vec::new(),
+        stats: Vec::new(),
+        steps: Vec::new(),
+        layout: None,
+    };
+    for _ in 0..opts.runs {
+        let mut g = make();
+        let started = Instant::now();
+        make_tha_maze(generation_type, &mut g, opts.braid_chance);
+        samples.times.push(started.elapsed());
+        samples.stats.push(stats::analyze(&g));
+        samples.steps.resize(g.len(), (0., 0.));
+        for (sum, (dx, dy)) in samples.steps.iter_mut().zip(parent_steps(&g)) {
+            *sum = (sum.0 + dx, sum.1 + dy);
+        }
+        if samples.layout.is_none() {
+            let layout: Vec<(usize, usize)> = (0..g.len())
+                .map(|ix| (g.cell(ix).row(), g.cell(ix).col()))
+                .collect();
+            let mut seen = layout.clone();
+            seen.sort_unstable();
+            seen.dedup();
+            samples.layout = Some(layout).filter(|l| seen.len() == l.len());
+        }
+    }
+    samples
+}
+
+fn run_one(maze_type: &MazeType, generation_type: &GenerationType, opts: &BenchOptions) -> Samples {
+    let (rows, cols) = (opts.rows, opts.cols);
+    match maze_type {
+        MazeType::Regular => sample(
+            || rectangle::RegularGrid::new(rows, cols),
+            generation_type,
+            opts,
+        ),
+        MazeType::Circular => sample(
+            || polar::CircularGrid::with_options(rows, &PolarOptions::default()),
+            generation_type,
+            opts,
+        ),
+        MazeType::Hexagonal => sample(
+            || hexagonal::HexagonalGrid::masked(Mask::new(rows, cols)),
+            generation_type,
+            opts,
+        ),
+        MazeType::Delta => sample(
+            || delta::DeltaGrid::masked(Mask::new(rows, cols)),
+            generation_type,
+            opts,
+        ),
+        MazeType::Upsilon => sample(
+            || upsilon::UpsilonGrid::masked(Mask::new(rows, cols)),
+            generation_type,
+            opts,
+        ),
+        MazeType::Weave => sample(
+            || {
+                let mut g = weave::WeaveGrid::new(rows, cols);
+                if *generation_type == GenerationType::Kruskal {
+                    g.add_crossings(&mut rand::rng(), rows * cols);
+                }
+                g
+            },
+            generation_type,
+            opts,
+        ),
+        MazeType::ThreeD => sample(
+            || grid3d::Grid3D::new(opts.levels, rows, cols),
+            generation_type,
+            opts,
+        ),
+        MazeType::Cube => sample(|| cube::CubeGrid::new(rows), generation_type, opts),
+        MazeType::Sphere => sample(|| sphere::SphereGrid::new(rows), generation_type, opts),
+    }
+}
+
+/// Mean, standard deviation, smallest and largest value.
+fn summary(values: &[f64]) -> (f64, f64, f64, f64) {
+    let n = values.len().max(1) as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean) * (v - mean)).sum::<f64>() / n;
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    (mean, variance.sqrt(), min, max)
+}
+
+fn write_summary(out: &mut String, name: &str, values: &[f64]) {
+    let (mean, sd, min, max) = summary(values);
+    writeln!(
+        out,
+        "  {}: mean {:.2}, sd {:.2}, range {:.0}-{:.0}",
+        name, mean, sd, min, max
+    )
+    .unwrap();
+}
+
+fn write_histogram(out: &mut String, name: &str, values: &[usize]) {
+    let (Some(&min), Some(&max)) = (values.iter().min(), values.iter().max()) else {
+        return;
+    };
+    let width = (max - min) / HISTOGRAM_BUCKETS + 1;
+    let mut counts = [0; HISTOGRAM_BUCKETS];
+    for v in values {
+        counts[(v - min) / width] += 1;
+    }
+    let most = counts.iter().copied().max().unwrap_or(1);
+    writeln!(out, "  {} histogram:", name).unwrap();
+    for (i, &count) in counts.iter().enumerate() {
+        let from = min + i * width;
+        if from > max {
+            break;
+        }
+        writeln!(
+            out,
+            "    {:>5}-{:<5} {:<w$} {}",
+            from,
+            from + width - 1,
+            "#".repeat(count * HISTOGRAM_WIDTH / most),
+            count,
+            w = HISTOGRAM_WIDTH
+        )
+        .unwrap();
+    }
+}
+
+/// Per cell, how far the mean parent step of `s` is from that of the Aldous-Broder
+/// `baseline`. Its trees are uniformly random, so they only lean towards the first cell,
+/// which every generator shares.
+fn lean(s: &Samples, baseline: &Samples) -> Vec<(f64, f64)> {
+    let runs = s.times.len().max(1) as f64;
+    let baseline_runs = baseline.times.len().max(1) as f64;
+    s.steps
+        .iter()
+        .zip(&baseline.steps)
+        .map(|(a, b)| {
+            (
+                a.0 / runs - b.0 / baseline_runs,
+                a.1 / runs - b.1 / baseline_runs,
+            )
+        })
+        .collect()
+}
+
+fn mean_lean(lean: &[(f64, f64)]) -> (f64, f64) {
+    let cells = lean.len().max(1) as f64;
+    let (x, y) = lean
+        .iter()
+        .fold((0., 0.), |(x, y), (dx, dy)| (x + dx, y + dy));
+    (x / cells, y / cells)
+}
+
+/// Arrow for the way a cell leans, `·` where it hardly leans at all. The noise of a lean
+/// shrinks with the square root of the number of runs.
+fn bias_glyph((dx, dy): (f64, f64), runs: usize) -> char {
+    if dx.hypot(dy) < MIN_BIAS.max(2. / (runs.max(1) as f64).sqrt()) {
+        return '·';
+    }
+    let sector = (dy.atan2(dx) / (PI / 4.)).round().rem_euclid(8.) as usize;
+    ARROWS[sector]
+}
+
+/// One arrow per cell laid out by row and column, pointing the way the cell leans.
+fn write_heatmap(out: &mut String, lean: &[(f64, f64)], layout: &[(usize, usize)], runs: usize) {
+    let mut rows: HashMap<usize, Vec<(usize, char)>> = HashMap::new();
+    for (&b, &(row, col)) in lean.iter().zip(layout) {
+        rows.entry(row)
+            .or_default()
+            .push((col, bias_glyph(b, runs)));
+    }
+    let mut row_ids: Vec<usize> = rows.keys().copied().collect();
+    row_ids.sort_unstable();
+    writeln!(
+        out,
+        "  lean per cell against Aldous-Broder (arrows where the way back to the first cell \
+         leans one way, · where it does not):"
+    )
+    .unwrap();
+    for row in row_ids {
+        let cells = rows.get_mut(&row).unwrap();
+        cells.sort_unstable();
+        let mut line = vec![' '; cells.last().map_or(0, |c| c.0 + 1)];
+        for &(col, glyph) in cells.iter() {
+            line[col] = glyph;
+        }
+        writeln!(out, "    |{}|", line.iter().collect::<String>()).unwrap();
+    }
+}
+
+fn report(
+    out: &mut String,
+    maze_type: &MazeType,
+    generation_type: &GenerationType,
+    s: &Samples,
+    baseline: &Samples,
+) {
+    writeln!(
+        out,
+        "{:?} maze, {:?}, {} runs",
+        maze_type,
+        generation_type,
+        s.times.len()
+    )
+    .unwrap();
+    let millis: Vec<f64> = s.times.iter().map(|t| t.as_secs_f64() * 1000.).collect();
+    let (mean, _, min, max) = summary(&millis);
+    writeln!(
+        out,
+        "  generation time: mean {:.3} ms, range {:.3}-{:.3} ms",
+        mean, min, max
+    )
+    .unwrap();
+    let figure = |f: fn(&MazeStats) -> f64| s.stats.iter().map(f).collect::<Vec<f64>>();
+    write_summary(out, "dead ends", &figure(|st| st.dead_ends as f64));
+    write_summary(out, "dead end %", &figure(|st| 100. * st.dead_end_ratio()));
+    write_summary(out, "junctions", &figure(|st| st.junctions as f64));
+    write_summary(out, "average corridor", &figure(|st| st.average_corridor));
+    write_summary(out, "river %", &figure(|st| 100. * st.river));
+    write_summary(out, "longest path", &figure(|st| st.longest_path as f64));
+    write_summary(out, "solution", &figure(|st| st.solution as f64));
+    let longest: Vec<usize> = s.stats.iter().map(|st| st.longest_path).collect();
+    write_histogram(out, "longest path", &longest);
+    let dead_ends: Vec<usize> = s.stats.iter().map(|st| st.dead_ends).collect();
+    write_histogram(out, "dead ends", &dead_ends);
+
+    let lean = lean(s, baseline);
+    let (x, y) = mean_lean(&lean);
+    writeln!(
+        out,
+        "  mean lean against Aldous-Broder: ({:+.3}, {:+.3}) (x right, y down)",
+        x, y
+    )
+    .unwrap();
+    if let Some(layout) = &s.layout {
+        write_heatmap(out, &lean, layout, s.times.len());
+    }
+    writeln!(out).unwrap();
+}
+
+/// Generates `runs` mazes for every chosen maze type and generator and reports timings, the
+/// spread of their statistics and, cell by cell, how their trees lean compared to as many
+/// Aldous-Broder mazes. Generators needing compass directions are skipped on the other maze
+/// types.
+pub fn run(opts: &BenchOptions) -> String {
+    let mut out = String::new();
+    for maze_type in &opts.maze_types {
+        let baseline = run_one(maze_type, &GenerationType::AldousBroder, opts);
+        for generation_type in &opts.generation_types {
+            if generation_type.needs_compass() && !maze_type.has_compass() {
+                continue;
+            }
+            let samples = run_one(maze_type, generation_type, opts);
+            report(&mut out, maze_type, generation_type, &samples, &baseline);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts() -> BenchOptions {
+        BenchOptions {
+            runs: 40,
+            rows: 10,
+            cols: 10,
+            ..BenchOptions::default()
+        }
+    }
+
+    /// How many cells have an arrow in the heatmap, and the mean lean.
+    fn heatmap(generation_type: &GenerationType, baseline: &Samples) -> (usize, f64) {
+        let s = run_one(&MazeType::Regular, generation_type, &opts());
+        let lean = lean(&s, baseline);
+        let arrows = lean.iter().filter(|&&l| bias_glyph(l, 40) != '·').count();
+        let (x, y) = mean_lean(&lean);
+        (arrows, x.hypot(y))
+    }
+
+    #[test]
+    fn binary_tree_leans_where_aldous_broder_does_not() {
+        let baseline = run_one(&MazeType::Regular, &GenerationType::AldousBroder, &opts());
+        let (arrows, mean) = heatmap(&GenerationType::BinaryTree, &baseline);
+        assert!(
+            arrows > 70 && mean > 0.3,
+            "{} arrows, mean {}",
+            arrows,
+            mean
+        );
+        let (arrows, mean) = heatmap(&GenerationType::AldousBroder, &baseline);
+        assert!(
+            arrows < 30 && mean < 0.15,
+            "{} arrows, mean {}",
+            arrows,
+            mean
+        );
+    }
+}
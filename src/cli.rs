@@ -0,0 +1,573 @@
+use crate::bench::BenchOptions;
+use crate::difficulty::{self, DifficultyBand};
+use crate::draw_utils::{make_tha_maze, GenerationType, MazeType, LEVELS};
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition};
+use crate::mask::Mask;
+use crate::polar::PolarOptions;
+use crate::rectangle::Wrap;
+use crate::text::{CellMarks, TextDrawable};
+use crate::tiled::{TileMap, TileOptions};
+use crate::{
+    bench, cube, delta, export, grid3d, hexagonal, polar, rectangle, solve, sphere, stats, upsilon,
+    weave,
+};
+use std::path::Path;
+
+const USAGE: &str = "usage: rust-mazes export [options]
+
+options:
+  --maze rect|polar|hex|delta|upsilon|weave|3d|cube|sphere
+                                     grid type (default rect); upsilon mazes mix octagons
+                                     and squares, weave mazes have passages crossing under
+                                     each other, 3d mazes have stairs between stacked levels
+  --algo backtracker|aldous-broder|prim|true-prim|kruskal|binary-tree|sidewinder
+                                     generation algorithm (default backtracker);
+                                     binary-tree and sidewinder only for rect and weave
+  --size ROWSxCOLS                   grid size, just ROWS for polar, cube (cells along a
+                                     face) and sphere (rings per hemisphere) (default 20x20)
+  --levels N                         number of levels in 3d mazes (default 3)
+  --hub N                            rings left empty in the middle of polar mazes
+                                     (default 0, a single centre cell)
+  --first-ring N                     cells in the innermost ring of polar mazes, 2 or more
+                                     (default chosen like the other rings)
+  --max-aspect X                     split polar cells once they get X times wider than
+                                     tall, at least 1 (default 1.5)
+  --braid N                          braid chance 0-255 (default 0)
+  --difficulty easy|medium|hard|MIN-MAX
+                                     retry generation, braiding more or less, until the
+                                     difficulty score (0-100) is within the band; too easy
+                                     mazes without braiding switch to backtracker
+  --input FILE                       read a rect maze drawn as text instead of generating
+                                     one; border openings are entrances unless --wrap joins
+                                     that border
+  --wrap none|cylinder|torus|moebius
+                                     join opposite borders of rect mazes (default none)
+  --mask FILE                        only use the cells enabled in a mask, given as text (X
+                                     disables a cell) or as an image (dark pixels disable
+                                     cells); sets the size, only for rect, hex, delta and
+                                     upsilon mazes
+  --shape rect|hexagon|triangle      outline of the maze (default rect): a hexagon of hex
+                                     cells ROWS wide from corner to corner (odd sizes,
+                                     as in the GUI), or a triangle of delta cells ROWS tall
+  --format dot|graphml|text|ascii|tmx|tiled-json|stats|difficulty
+                                     output format (default dot); text only for rect, hex
+                                     and delta mazes, ascii only for rect, tmx and
+                                     tiled-json for rect and hex; stats prints dead ends,
+                                     corridor and path lengths, loops and more as JSON;
+                                     difficulty prints the difficulty score and its parts
+  --positions                        include node positions (for `neato -n`)
+  --solution                         mark the longest path in text output
+  --distances                        print distances from the start in text output
+  --tile-block N                     tiles per cell side in Tiled maps, borders shared with
+                                     the neighbours (default 3)
+  --tile-ids FLOOR,WALL              tile ids for Tiled maps (default 1,2)
+  --markers                          add start/end objects to Tiled maps
+
+usage: rust-mazes bench [options]
+
+Generates mazes with every generator and reports timings, statistics and, cell by cell, how
+far their trees lean one way compared to Aldous-Broder mazes.
+
+options:
+  --runs N                           mazes per maze type and generator (default 10)
+  --maze TYPE,TYPE,...               maze types to compare (default all)
+  --algo ALGO,ALGO,...               generators to compare (default all); binary-tree and
+                                     sidewinder are skipped for maze types other than rect
+                                     and weave
+  --size ROWSxCOLS                   grid size as for export (default 20x20)
+  --levels N                         number of levels in 3d mazes (default 3)
+  --braid N                          braid chance 0-255 (default 0)";
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Shape {
+    Rectangle,
+    Hexagon,
+    Triangle,
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    Dot,
+    GraphMl,
+    Text,
+    Ascii,
+    Tmx,
+    TiledJson,
+    Stats,
+    Difficulty,
+}
+
+pub struct CliOptions {
+    pub maze_type: MazeType,
+    pub generation_type: GenerationType,
+    pub rows: usize,
+    pub cols: usize,
+    pub levels: usize,
+    pub polar: PolarOptions,
+    pub braid_chance: u8,
+    pub difficulty: Option<DifficultyBand>,
+    pub format: OutputFormat,
+    pub positions: bool,
+    pub marks: CellMarks,
+    pub input: Option<String>,
+    pub mask: Option<Mask>,
+    pub shape: Shape,
+    pub wrap: Wrap,
+    pub tiles: TileOptions,
+}
+
+impl Default for CliOptions {
+    fn default() -> CliOptions {
+        CliOptions {
+            maze_type: MazeType::Regular,
+            generation_type: GenerationType::RecursiveBacktracker,
+            rows: 20,
+            cols: 20,
+            levels: LEVELS,
+            polar: PolarOptions::default(),
+            braid_chance: 0,
+            difficulty: None,
+            format: OutputFormat::Dot,
+            positions: false,
+            marks: CellMarks::Nothing,
+            input: None,
+            mask: None,
+            shape: Shape::Rectangle,
+            wrap: Wrap::None,
+            tiles: TileOptions::default(),
+        }
+    }
+}
+
+fn parse_maze_type(s: &str) -> Result<MazeType, String> {
+    match s {
+        "rect" => Ok(MazeType::Regular),
+        "polar" => Ok(MazeType::Circular),
+        "hex" => Ok(MazeType::Hexagonal),
+        "delta" => Ok(MazeType::Delta),
+        "weave" => Ok(MazeType::Weave),
+        "3d" => Ok(MazeType::ThreeD),
+        "cube" => Ok(MazeType::Cube),
+        "sphere" => Ok(MazeType::Sphere),
+        "upsilon" => Ok(MazeType::Upsilon),
+        _ => Err(format!("unknown maze type {:?}", s)),
+    }
+}
+
+fn parse_generation_type(s: &str) -> Result<GenerationType, String> {
+    match s {
+        "backtracker" => Ok(GenerationType::RecursiveBacktracker),
+        "aldous-broder" => Ok(GenerationType::AldousBroder),
+        "prim" => Ok(GenerationType::SimplifiedPrim),
+        "true-prim" => Ok(GenerationType::TruePrim),
+        "kruskal" => Ok(GenerationType::Kruskal),
+        "binary-tree" => Ok(GenerationType::BinaryTree),
+        "sidewinder" => Ok(GenerationType::Sidewinder),
+        _ => Err(format!("unknown algorithm {:?}", s)),
+    }
+}
+
+fn parse_wrap(s: &str) -> Result<Wrap, String> {
+    match s {
+        "none" => Ok(Wrap::None),
+        "cylinder" => Ok(Wrap::Cylinder),
+        "torus" => Ok(Wrap::Torus),
+        "moebius" => Ok(Wrap::Moebius),
+        _ => Err(format!("unknown wrap {:?}", s)),
+    }
+}
+
+fn parse_shape(s: &str) -> Result<Shape, String> {
+    match s {
+        "rect" => Ok(Shape::Rectangle),
+        "hexagon" => Ok(Shape::Hexagon),
+        "triangle" => Ok(Shape::Triangle),
+        _ => Err(format!("unknown shape {:?}", s)),
+    }
+}
+
+fn parse_format(s: &str) -> Result<OutputFormat, String> {
+    match s {
+        "dot" => Ok(OutputFormat::Dot),
+        "graphml" => Ok(OutputFormat::GraphMl),
+        "text" => Ok(OutputFormat::Text),
+        "ascii" => Ok(OutputFormat::Ascii),
+        "tmx" => Ok(OutputFormat::Tmx),
+        "tiled-json" => Ok(OutputFormat::TiledJson),
+        "stats" => Ok(OutputFormat::Stats),
+        "difficulty" => Ok(OutputFormat::Difficulty),
+        _ => Err(format!("unknown format {:?}", s)),
+    }
+}
+
+fn parse_difficulty(s: &str) -> Result<DifficultyBand, String> {
+    match s {
+        "easy" => return Ok(difficulty::EASY),
+        "medium" => return Ok(difficulty::MEDIUM),
+        "hard" => return Ok(difficulty::HARD),
+        _ => {}
+    }
+    let band = s.split_once('-').and_then(|(min, max)| {
+        Some(DifficultyBand {
+            min: min.parse().ok()?,
+            max: max.parse().ok()?,
+        })
+    });
+    match band {
+        Some(band) if band.min <= band.max => Ok(band),
+        _ => Err(format!("invalid difficulty {:?}", s)),
+    }
+}
+
+fn parse_size(s: &str) -> Result<(usize, usize), String> {
+    let parse = |v: &str| match v.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("invalid size {:?}", s)),
+    };
+    match s.split_once('x') {
+        Some((rows, cols)) => Ok((parse(rows)?, parse(cols)?)),
+        None => Ok((parse(s)?, 0)),
+    }
+}
+
+pub fn parse_args(args: &[String]) -> Result<CliOptions, String> {
+    let mut opts = CliOptions::default();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || {
+            it.next()
+                .map(|v| v.as_str())
+                .ok_or(format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--maze" => opts.maze_type = parse_maze_type(value()?)?,
+            "--algo" => opts.generation_type = parse_generation_type(value()?)?,
+            "--size" => {
+                let (rows, cols) = parse_size(value()?)?;
+                opts.rows = rows;
+                opts.cols = if cols == 0 { rows } else { cols };
+            }
+            "--levels" => {
+                let v = value()?;
+                opts.levels = match v.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid levels {:?}", v)),
+                }
+            }
+            "--hub" => {
+                let v = value()?;
+                opts.polar.hub = v.parse().map_err(|_| format!("invalid hub {:?}", v))?
+            }
+            "--first-ring" => {
+                let v = value()?;
+                opts.polar.first_ring = match v.parse() {
+                    Ok(n) if n >= 2 => Some(n),
+                    _ => return Err(format!("invalid first ring {:?}, need 2 or more", v)),
+                }
+            }
+            "--max-aspect" => {
+                let v = value()?;
+                opts.polar.max_aspect = match v.parse() {
+                    Ok(x) if x >= 1. => x,
+                    _ => return Err(format!("invalid max aspect {:?}, need 1 or more", v)),
+                }
+            }
+            "--braid" => {
+                let v = value()?;
+                opts.braid_chance = v.parse().map_err(|_| format!("invalid braid {:?}", v))?
+            }
+            "--difficulty" => opts.difficulty = Some(parse_difficulty(value()?)?),
+            "--input" => opts.input = Some(value()?.to_string()),
+            "--mask" => opts.mask = Some(Mask::from_file(Path::new(value()?))?),
+            "--wrap" => opts.wrap = parse_wrap(value()?)?,
+            "--shape" => opts.shape = parse_shape(value()?)?,
+            "--format" => opts.format = parse_format(value()?)?,
+            "--positions" => opts.positions = true,
+            "--solution" => opts.marks = CellMarks::Solution,
+            "--distances" => opts.marks = CellMarks::Distances,
+            "--tile-block" => {
+                let v = value()?;
+                opts.tiles.block = match v.parse() {
+                    Ok(n) if n >= 3 => n,
+                    _ => return Err(format!("invalid tile block {:?}, need 3 or more", v)),
+                }
+            }
+            "--tile-ids" => {
+                let v = value()?;
+                let ids: Result<Vec<u32>, _> = v.split(',').map(|id| id.parse()).collect();
+                match ids.as_deref() {
+                    Ok(&[floor, wall]) => {
+                        opts.tiles.floor_gid = floor;
+                        opts.tiles.wall_gid = wall;
+                    }
+                    _ => return Err(format!("invalid tile ids {:?}", v)),
+                }
+            }
+            "--markers" => opts.tiles.markers = true,
+            _ => return Err(format!("unknown option {:?}", arg)),
+        }
+    }
+    Ok(opts)
+}
+
+/// Parses a comma separated list.
+fn parse_list<T>(s: &str, parse: fn(&str) -> Result<T, String>) -> Result<Vec<T>, String> {
+    s.split(',').map(parse).collect()
+}
+
+pub fn parse_bench_args(args: &[String]) -> Result<BenchOptions, String> {
+    let mut opts = BenchOptions::default();
+    let mut it = args.iter();
+    while let Some(arg) = it.next() {
+        let mut value = || {
+            it.next()
+                .map(|v| v.as_str())
+                .ok_or(format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--runs" => {
+                let v = value()?;
+                opts.runs = match v.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid runs {:?}", v)),
+                }
+            }
+            "--maze" => opts.maze_types = parse_list(value()?, parse_maze_type)?,
+            "--algo" => opts.generation_types = parse_list(value()?, parse_generation_type)?,
+            "--size" => {
+                let (rows, cols) = parse_size(value()?)?;
+                opts.rows = rows;
+                opts.cols = if cols == 0 { rows } else { cols };
+            }
+            "--levels" => {
+                let v = value()?;
+                opts.levels = match v.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => return Err(format!("invalid levels {:?}", v)),
+                }
+            }
+            "--braid" => {
+                let v = value()?;
+                opts.braid_chance = v.parse().map_err(|_| format!("invalid braid {:?}", v))?
+            }
+            _ => return Err(format!("unknown option {:?}", arg)),
+        }
+    }
+    Ok(opts)
+}
+
+fn render_graph<C: AbstractCell, T: AbstractGrid<C> + CellPosition>(
+    g: &T,
+    opts: &CliOptions,
+) -> Result<String, String> {
+    match opts.format {
+        OutputFormat::Dot => Ok(export::to_dot(g, opts.positions)),
+        OutputFormat::GraphMl => Ok(export::to_graphml(g, opts.positions)),
+        OutputFormat::Stats => Ok(stats::analyze(g).to_json() + "\n"),
+        OutputFormat::Difficulty => Ok(difficulty::rate(g).to_json() + "\n"),
+        _ => Err(format!(
+            "{:?} output is not available for {:?} mazes",
+            opts.format, opts.maze_type
+        )),
+    }
+}
+
+fn render<C: AbstractCell, T: AbstractGrid<C> + CellPosition + TextDrawable>(
+    g: &T,
+    opts: &CliOptions,
+) -> Result<String, String> {
+    match opts.format {
+        OutputFormat::Text => Ok(g.to_text(&solve::solve_with_longest_path(g), &opts.marks)),
+        _ => render_graph(g, opts),
+    }
+}
+
+fn render_tiles(map: TileMap, opts: &CliOptions) -> String {
+    match opts.format {
+        OutputFormat::TiledJson => map.to_json(),
+        _ => map.to_tmx(),
+    }
+}
+
+fn render_regular(g: &rectangle::RegularGrid, opts: &CliOptions) -> Result<String, String> {
+    match opts.format {
+        OutputFormat::Ascii => Ok(g.to_string()),
+        OutputFormat::Tmx | OutputFormat::TiledJson => {
+            let step = solve::solve_with_longest_path(g);
+            let map = TileMap::from_regular(g, &step, &opts.tiles);
+            Ok(render_tiles(map, opts))
+        }
+        _ => render(g, opts),
+    }
+}
+
+fn read_maze(path: &str, wrap: &Wrap) -> Result<rectangle::RegularGrid, String> {
+    let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    rectangle::RegularGrid::parse_wrapped(&text, wrap.clone())
+        .map_err(|e| format!("{}: {}", path, e))
+}
+
+fn grid_mask(opts: &CliOptions) -> Mask {
+    match (&opts.mask, &opts.shape) {
+        (Some(mask), _) => mask.clone(),
+        (None, Shape::Hexagon) => hexagonal::hexagon_mask(opts.rows),
+        (None, Shape::Triangle) => delta::triangle_mask(opts.rows),
+        (None, Shape::Rectangle) => Mask::new(opts.rows, opts.cols),
+    }
+}
+
+/// Number of mazes `--difficulty` tries before giving up.
+const DIFFICULTY_ATTEMPTS: usize = 50;
+
+/// Generates a maze on a grid from `make`, retrying until it is within `--difficulty`.
+fn generate<C: AbstractCell, T: AbstractGrid<C>>(
+    opts: &CliOptions,
+    make: impl Fn() -> T,
+) -> Result<T, String> {
+    let Some(band) = &opts.difficulty else {
+        let mut g = make();
+        make_tha_maze(&opts.generation_type, &mut g, opts.braid_chance);
+        return Ok(g);
+    };
+    let (g, rating) = difficulty::generate_within(
+        make,
+        &opts.generation_type,
+        opts.braid_chance,
+        band,
+        DIFFICULTY_ATTEMPTS,
+    );
+    if band.contains(rating.score) {
+        Ok(g)
+    } else {
+        Err(format!(
+            "no maze within difficulty {} after {} attempts, the closest scored {:.1}",
+            band, DIFFICULTY_ATTEMPTS, rating.score
+        ))
+    }
+}
+
+fn generate_and_render(opts: &CliOptions) -> Result<String, String> {
+    if let Some(path) = &opts.input {
+        if opts.maze_type != MazeType::Regular {
+            return Err("only rect mazes can be read from text".to_string());
+        }
+        if opts.difficulty.is_some() {
+            return Err("--difficulty can not be used with --input".to_string());
+        }
+        return render_regular(&read_maze(path, &opts.wrap)?, opts);
+    }
+    match (&opts.shape, &opts.maze_type) {
+        (Shape::Rectangle, _) | (Shape::Hexagon, MazeType::Hexagonal) => {}
+        (Shape::Triangle, MazeType::Delta) => {}
+        (shape, maze_type) => {
+            return Err(format!(
+                "{:?} shape is not available for {:?} mazes",
+                shape, maze_type
+            ))
+        }
+    }
+    if opts.shape != Shape::Rectangle && opts.mask.is_some() {
+        return Err("--shape and --mask can not be used together".to_string());
+    }
+    if opts.generation_type.needs_compass() && !opts.maze_type.has_compass() {
+        return Err(format!(
+            "{:?} is only available for rect and weave mazes",
+            opts.generation_type
+        ));
+    }
+    match opts.maze_type {
+        MazeType::Regular => {
+            let g = generate(opts, || {
+                let mut g = rectangle::RegularGrid::masked(grid_mask(opts));
+                g.wrap = opts.wrap.clone();
+                g
+            })?;
+            render_regular(&g, opts)
+        }
+        MazeType::Circular => {
+            if opts.mask.is_some() {
+                return Err("polar mazes can not be masked".to_string());
+            }
+            let g = generate(opts, || {
+                polar::CircularGrid::with_options(opts.rows, &opts.polar)
+            })?;
+            render_graph(&g, opts)
+        }
+        MazeType::Hexagonal => {
+            let g = generate(opts, || hexagonal::HexagonalGrid::masked(grid_mask(opts)))?;
+            match opts.format {
+                OutputFormat::Tmx | OutputFormat::TiledJson => {
+                    let step = solve::solve_with_longest_path(&g);
+                    let map = TileMap::from_hexagonal(&g, &step, &opts.tiles);
+                    Ok(render_tiles(map, opts))
+                }
+                _ => render(&g, opts),
+            }
+        }
+        MazeType::Delta => {
+            let g = generate(opts, || delta::DeltaGrid::masked(grid_mask(opts)))?;
+            render(&g, opts)
+        }
+        MazeType::Upsilon => {
+            let g = generate(opts, || upsilon::UpsilonGrid::masked(grid_mask(opts)))?;
+            render_graph(&g, opts)
+        }
+        MazeType::Weave => {
+            if opts.mask.is_some() {
+                return Err("weave mazes can not be masked".to_string());
+            }
+            let g = generate(opts, || {
+                let mut g = weave::WeaveGrid::new(opts.rows, opts.cols);
+                if opts.generation_type == GenerationType::Kruskal {
+                    g.add_crossings(&mut rand::rng(), opts.rows * opts.cols);
+                }
+                g
+            })?;
+            render_graph(&g, opts)
+        }
+        MazeType::ThreeD => {
+            if opts.mask.is_some() {
+                return Err("3d mazes can not be masked".to_string());
+            }
+            let g = generate(opts, || {
+                grid3d::Grid3D::new(opts.levels, opts.rows, opts.cols)
+            })?;
+            render_graph(&g, opts)
+        }
+        MazeType::Cube => {
+            if opts.mask.is_some() {
+                return Err("cube mazes can not be masked".to_string());
+            }
+            let g = generate(opts, || cube::CubeGrid::new(opts.rows))?;
+            render_graph(&g, opts)
+        }
+        MazeType::Sphere => {
+            if opts.mask.is_some() {
+                return Err("sphere mazes can not be masked".to_string());
+            }
+            let g = generate(opts, || sphere::SphereGrid::new(opts.rows))?;
+            render_graph(&g, opts)
+        }
+    }
+}
+
+/// Entry point for command line use; `args` excludes the program name.
+pub fn run(args: &[String]) -> Result<(), String> {
+    match args.first().map(|s| s.as_str()) {
+        Some("export") => {
+            let opts = parse_args(&args[1..])?;
+            print!("{}", generate_and_render(&opts)?);
+            Ok(())
+        }
+        Some("bench") => {
+            let opts = parse_bench_args(&args[1..])?;
+            print!("{}", bench::run(&opts));
+            Ok(())
+        }
+        Some("help") | Some("--help") => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(USAGE.to_string()),
+    }
+}
@@ -0,0 +1,426 @@
+use crate::draw_utils::{MazeType, MAX_SIZE};
+use crate::grid::{AbstractCell, AbstractGrid};
+use crate::mask::Mask;
+use crate::rectangle::Wrap;
+use std::fmt::{Display, Error, Formatter};
+
+// Layout of a maze code (before base64):
+//   byte 0      format version
+//   byte 1      maze type
+//   bytes 2..4  rows, big endian
+//   bytes 4..6  cols, big endian (0 for polar and sphere grids)
+//   byte 6      flags (since version 2): bit 0 set for masked grids, bits 1-2 the wrap
+//               mode of rectangular grids (none, cylinder, torus, Moebius)
+//   mask        rows * cols bits, row by row, padded to a whole byte (only when masked)
+//   rest        one bit per edge, MSB first
+//
+// Edges are enumerated cell by cell, visiting for every cell the neighbours with a
+// greater index in `neighbours` order. For RegularGrid that is one bit for the east wall
+// and one for the south wall of every cell; other grids get the same per-edge packing.
+pub const CODE_VERSION: u8 = 2;
+const FLAG_MASKED: u8 = 1;
+const WRAP_SHIFT: u8 = 1;
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CodeError {
+    InvalidCharacter(char),
+    TooShort,
+    EmptyGrid,
+    TooLarge { rows: usize, cols: usize },
+    UnexpectedMask(MazeType),
+    UnsupportedVersion(u8),
+    UnknownMazeType(u8),
+    LengthMismatch { expected: usize, actual: usize },
+}
+
+impl Display for CodeError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        match self {
+            CodeError::InvalidCharacter(c) => write!(f, "invalid character {:?} in maze code", c),
+            CodeError::TooShort => write!(f, "maze code is too short"),
+            CodeError::EmptyGrid => write!(f, "maze code describes an empty grid"),
+            CodeError::TooLarge { rows, cols } => {
+                write!(f, "a {}x{} maze is too large for a maze code", rows, cols)
+            }
+            CodeError::UnexpectedMask(t) => write!(f, "{:?} mazes can not be masked", t),
+            CodeError::UnsupportedVersion(v) => write!(f, "unsupported maze code version {}", v),
+            CodeError::UnknownMazeType(t) => write!(f, "unknown maze type {}", t),
+            CodeError::LengthMismatch { expected, actual } => write!(
+                f,
+                "maze code has {} bytes of walls, expected {}",
+                actual, expected
+            ),
+        }
+    }
+}
+
+/// Header and raw edge bits of a decoded maze code.
+pub struct MazeCode {
+    pub maze_type: MazeType,
+    pub rows: usize,
+    pub cols: usize,
+    pub mask: Option<Mask>,
+    pub wrap: Wrap,
+    pub edges: Vec<u8>,
+}
+
+pub fn maze_type_id(maze_type: &MazeType) -> u8 {
+    match maze_type {
+        MazeType::Regular => 0,
+        MazeType::Circular => 1,
+        MazeType::Hexagonal => 2,
+        MazeType::Delta => 3,
+        // reserved, weave mazes are not encoded yet
+        MazeType::Weave => 4,
+        // reserved, 3D mazes are not encoded yet
+        MazeType::ThreeD => 5,
+        MazeType::Cube => 6,
+        MazeType::Sphere => 7,
+        MazeType::Upsilon => 8,
+    }
+}
+
+fn maze_type_from_id(id: u8) -> Result<MazeType, CodeError> {
+    match id {
+        0 => Ok(MazeType::Regular),
+        1 => Ok(MazeType::Circular),
+        2 => Ok(MazeType::Hexagonal),
+        3 => Ok(MazeType::Delta),
+        6 => Ok(MazeType::Cube),
+        7 => Ok(MazeType::Sphere),
+        8 => Ok(MazeType::Upsilon),
+        // 4 and 5 are reserved for weave and 3D mazes
+        _ => Err(CodeError::UnknownMazeType(id)),
+    }
+}
+
+fn wrap_id(wrap: &Wrap) -> u8 {
+    match wrap {
+        Wrap::None => 0,
+        Wrap::Cylinder => 1,
+        Wrap::Torus => 2,
+        Wrap::Moebius => 3,
+    }
+}
+
+fn wrap_from_id(id: u8) -> Wrap {
+    match id & 3 {
+        1 => Wrap::Cylinder,
+        2 => Wrap::Torus,
+        3 => Wrap::Moebius,
+        _ => Wrap::None,
+    }
+}
+
+fn edge_count<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(g: &T) -> usize {
+    (0..g.len())
+        .map(|i| g.neighbours(i).iter().filter(|&&n| n > i).count())
+        .sum()
+}
+
+/// Fails when the dimensions are beyond `MAX_SIZE`, which `decode` would not accept.
+pub fn encode<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+    maze_type: &MazeType,
+    rows: usize,
+    cols: usize,
+    mask: Option<&Mask>,
+    wrap: &Wrap,
+) -> Result<String, CodeError> {
+    if rows > MAX_SIZE || cols > MAX_SIZE {
+        return Err(CodeError::TooLarge { rows, cols });
+    }
+    let mut bytes = vec![CODE_VERSION, maze_type_id(maze_type)];
+    bytes.extend_from_slice(&(rows as u16).to_be_bytes());
+    bytes.extend_from_slice(&(cols as u16).to_be_bytes());
+    let flags = wrap_id(wrap) << WRAP_SHIFT;
+    match mask {
+        Some(mask) => {
+            bytes.push(flags | FLAG_MASKED);
+            let mut bit = 0;
+            for row in 0..mask.rows {
+                for col in 0..mask.cols {
+                    if bit % 8 == 0 {
+                        bytes.push(0);
+                    }
+                    if mask.is_on(row, col) {
+                        *bytes.last_mut().unwrap() |= 0x80 >> (bit % 8);
+                    }
+                    bit += 1;
+                }
+            }
+        }
+        None => bytes.push(flags),
+    }
+
+    let mut bit = 0;
+    for i in 0..g.len() {
+        let links = g.links(i);
+        for n in g.neighbours(i).into_iter().filter(|&n| n > i) {
+            if bit % 8 == 0 {
+                bytes.push(0);
+            }
+            if links.contains(&n) {
+                *bytes.last_mut().unwrap() |= 0x80 >> (bit % 8);
+            }
+            bit += 1;
+        }
+    }
+    Ok(to_base64(&bytes))
+}
+
+/// Reads the header, and the mask if there is one. Codes are limited to `MAX_SIZE` rows and
+/// columns, so a short code can not make the caller build a huge grid, and only maze types
+/// that take masks can have one.
+pub fn decode(code: &str) -> Result<MazeCode, CodeError> {
+    let bytes = from_base64(code.trim())?;
+    if bytes.is_empty() {
+        return Err(CodeError::TooShort);
+    }
+    // version 1 codes have no flags byte
+    let header_len = match bytes[0] {
+        1 => 6,
+        CODE_VERSION => 7,
+        v => return Err(CodeError::UnsupportedVersion(v)),
+    };
+    if bytes.len() < header_len {
+        return Err(CodeError::TooShort);
+    }
+    let maze_type = maze_type_from_id(bytes[1])?;
+    let rows = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+    let cols = u16::from_be_bytes([bytes[4], bytes[5]]) as usize;
+    let rings = matches!(maze_type, MazeType::Circular | MazeType::Sphere);
+    if rows == 0 || (cols == 0 && !rings) {
+        return Err(CodeError::EmptyGrid);
+    }
+
+    if rows > MAX_SIZE || cols > MAX_SIZE {
+        return Err(CodeError::TooLarge { rows, cols });
+    }
+    let flags = if header_len > 6 { bytes[6] } else { 0 };
+    if flags & FLAG_MASKED != 0 && !maze_type.maskable() {
+        return Err(CodeError::UnexpectedMask(maze_type));
+    }
+    let mut edges_start = header_len;
+    let mut mask = None;
+    if flags & FLAG_MASKED != 0 {
+        edges_start += (rows * cols).div_ceil(8);
+        if bytes.len() < edges_start {
+            return Err(CodeError::TooShort);
+        }
+        let mut m = Mask::new(rows, cols);
+        for bit in 0..rows * cols {
+            let on = bytes[header_len + bit / 8] & (0x80 >> (bit % 8)) != 0;
+            m.set(bit / cols, bit % cols, on);
+        }
+        mask = Some(m);
+    }
+    Ok(MazeCode {
+        maze_type,
+        rows,
+        cols,
+        mask,
+        wrap: wrap_from_id(flags >> WRAP_SHIFT),
+        edges: bytes[edges_start..].to_vec(),
+    })
+}
+
+impl MazeCode {
+    /// Links `g` according to the edge bits. `g` must be a fresh grid built with the
+    /// dimensions from the header.
+    pub fn apply<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+        &self,
+        g: &mut T,
+    ) -> Result<(), CodeError> {
+        let expected = edge_count(g).div_ceil(8);
+        if self.edges.len() != expected {
+            return Err(CodeError::LengthMismatch {
+                expected,
+                actual: self.edges.len(),
+            });
+        }
+
+        let mut bit = 0;
+        for i in 0..g.len() {
+            for n in g.neighbours(i).into_iter().filter(|&n| n > i) {
+                if self.edges[bit / 8] & (0x80 >> (bit % 8)) != 0 {
+                    g.link(i, n);
+                }
+                bit += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn to_base64(bytes: &[u8]) -> String {
+    let mut res = String::new();
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+        for i in 0..=chunk.len() {
+            res.push(ALPHABET[((n >> (18 - 6 * i)) & 0x3f) as usize] as char);
+        }
+    }
+    res
+}
+
+fn from_base64(s: &str) -> Result<Vec<u8>, CodeError> {
+    let mut res = Vec::new();
+    let mut acc: u32 = 0;
+    let mut acc_bits = 0;
+    for c in s.chars() {
+        let v = ALPHABET
+            .iter()
+            .position(|&a| a as char == c)
+            .ok_or(CodeError::InvalidCharacter(c))?;
+        acc = (acc << 6) | v as u32;
+        acc_bits += 6;
+        if acc_bits >= 8 {
+            acc_bits -= 8;
+            res.push((acc >> acc_bits) as u8);
+            acc &= (1 << acc_bits) - 1;
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_utils::{make_tha_maze, GenerationType};
+    use crate::rectangle::RegularGrid;
+
+    fn maze(mask: Mask, wrap: Wrap) -> RegularGrid {
+        let mut g = RegularGrid::masked(mask);
+        g.wrap = wrap;
+        make_tha_maze(&GenerationType::RecursiveBacktracker, &mut g, 40);
+        g
+    }
+
+    fn round_trip(g: &RegularGrid, code: &str) -> MazeCode {
+        let decoded = decode(code).unwrap();
+        let mask = decoded
+            .mask
+            .clone()
+            .unwrap_or_else(|| Mask::new(decoded.rows, decoded.cols));
+        let mut copy = RegularGrid::masked(mask);
+        copy.wrap = decoded.wrap.clone();
+        decoded.apply(&mut copy).unwrap();
+        for ix in 0..g.len() {
+            assert_eq!(g.links(ix), copy.links(ix), "links of cell {}", ix);
+        }
+        decoded
+    }
+
+    #[test]
+    fn round_trips_plain_maze() {
+        let g = maze(Mask::new(7, 11), Wrap::None);
+        let code = encode(&g, &MazeType::Regular, 7, 11, None, &Wrap::None).unwrap();
+        let decoded = round_trip(&g, &code);
+        assert_eq!(decoded.maze_type, MazeType::Regular);
+        assert_eq!((decoded.rows, decoded.cols), (7, 11));
+        assert!(decoded.mask.is_none());
+    }
+
+    #[test]
+    fn round_trips_masks_and_wrap() {
+        let mask = Mask::from_text("..X..\n.XXX.\n.....\n..X..\n");
+        for wrap in [Wrap::Cylinder, Wrap::Torus, Wrap::Moebius] {
+            let g = maze(mask.clone(), wrap.clone());
+            let code = encode(&g, &MazeType::Regular, 4, 5, Some(&mask), &wrap).unwrap();
+            let decoded = round_trip(&g, &code);
+            assert_eq!(decoded.mask.as_ref(), Some(&mask));
+            assert_eq!(decoded.wrap, wrap);
+        }
+    }
+
+    #[test]
+    fn reads_version_1_codes() {
+        let g = maze(Mask::new(5, 6), Wrap::None);
+        let code = encode(&g, &MazeType::Regular, 5, 6, None, &Wrap::None).unwrap();
+        // version 1 had the same layout without the flags byte
+        let mut bytes = from_base64(&code).unwrap();
+        bytes[0] = 1;
+        bytes.remove(6);
+        let decoded = round_trip(&g, &to_base64(&bytes));
+        assert_eq!(decoded.wrap, Wrap::None);
+    }
+
+    #[test]
+    fn rejects_sizes_beyond_the_header() {
+        let g = RegularGrid::new(2, 2);
+        assert_eq!(
+            encode(&g, &MazeType::Regular, 70000, 2, None, &Wrap::None),
+            Err(CodeError::TooLarge {
+                rows: 70000,
+                cols: 2
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_reserved_maze_types() {
+        for id in [4, 5, 9] {
+            let bytes = [CODE_VERSION, id, 0, 3, 0, 3, 0, 0, 0];
+            assert_eq!(
+                decode(&to_base64(&bytes)).err(),
+                Some(CodeError::UnknownMazeType(id))
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_huge_grids_before_building_them() {
+        let bytes = [CODE_VERSION, 0, 0xff, 0xff, 0xff, 0xff, 0];
+        assert_eq!(
+            decode(&to_base64(&bytes)).err(),
+            Some(CodeError::TooLarge {
+                rows: 65535,
+                cols: 65535
+            })
+        );
+        let masked = [CODE_VERSION, 0, 0xff, 0xff, 0xff, 0xff, FLAG_MASKED, 0xff];
+        assert_eq!(
+            decode(&to_base64(&masked)).err(),
+            Some(CodeError::TooLarge {
+                rows: 65535,
+                cols: 65535
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_huge_ring_and_cube_codes() {
+        // polar and sphere codes have no columns, and cube codes only one
+        for (maze_type, id, cols) in [
+            (MazeType::Circular, 1, 0),
+            (MazeType::Cube, 6, 1),
+            (MazeType::Sphere, 7, 0),
+        ] {
+            for flags in [0, FLAG_MASKED] {
+                let bytes = [CODE_VERSION, id, 0xff, 0xff, 0, cols, flags];
+                assert_eq!(
+                    decode(&to_base64(&bytes)).err(),
+                    Some(CodeError::TooLarge {
+                        rows: 65535,
+                        cols: cols as usize
+                    }),
+                    "{:?}",
+                    maze_type
+                );
+            }
+            let bytes = [CODE_VERSION, id, 0, 4, 0, cols, FLAG_MASKED];
+            assert_eq!(
+                decode(&to_base64(&bytes)).err(),
+                Some(CodeError::UnexpectedMask(maze_type))
+            );
+        }
+    }
+}
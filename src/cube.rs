@@ -0,0 +1,356 @@
+use crate::draw_utils::{orthographic, polygon_path, GtkDrawable};
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition};
+use crate::gtk::prelude::WidgetExt;
+use crate::play::Move;
+use crate::solve::DijkstraStep;
+use gtk::cairo::Context;
+use gtk::DrawingArea;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+
+type Vec3 = [i64; 3];
+type Quad = [(f64, f64); 4];
+
+/// Corner (in multiples of the face size), column direction, row direction and outward
+/// normal of each face of a cube spanning 0..size on every axis. Faces are in net order:
+/// up, left, front, right, back, down.
+const FACES: [(Vec3, Vec3, Vec3, Vec3); 6] = [
+    ([0, 1, 0], [1, 0, 0], [0, 0, 1], [0, 1, 0]),
+    ([0, 1, 0], [0, 0, 1], [0, -1, 0], [-1, 0, 0]),
+    ([0, 1, 1], [1, 0, 0], [0, -1, 0], [0, 0, 1]),
+    ([1, 1, 1], [0, 0, -1], [0, -1, 0], [1, 0, 0]),
+    ([1, 1, 0], [-1, 0, 0], [0, -1, 0], [0, 0, -1]),
+    ([0, 0, 1], [1, 0, 0], [0, 0, -1], [0, -1, 0]),
+];
+
+/// Column and row of each face in the unfolded net, counted in faces:
+///
+/// ```text
+///    U
+///  L F R B
+///    D
+/// ```
+const NET: [(usize, usize); 6] = [(1, 0), (0, 1), (1, 1), (2, 1), (3, 1), (1, 2)];
+
+fn dot(a: Vec3, b: Vec3) -> i64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+#[derive(Clone)]
+pub struct CubeCell {
+    pub face: usize,
+    pub row: usize,
+    pub col: usize,
+    pub links: HashSet<usize>,
+}
+
+impl AbstractCell for CubeCell {
+    fn row(&self) -> usize {
+        self.row
+    }
+
+    fn col(&self) -> usize {
+        self.col
+    }
+
+    fn links(&self) -> HashSet<usize> {
+        self.links.iter().cloned().collect()
+    }
+
+    fn link(&mut self, ix: usize) {
+        self.links.insert(ix);
+    }
+
+    fn label(&self) -> String {
+        format!("(face {},{},{})", self.face, self.row, self.col)
+    }
+}
+
+/// Six square faces of `size` x `size` cells, with passages running over the cube's edges.
+#[derive(Clone)]
+pub struct CubeGrid {
+    pub size: usize,
+    pub cells: Vec<CubeCell>,
+}
+
+impl CubeGrid {
+    pub fn new(size: usize) -> CubeGrid {
+        let mut cells = Vec::new();
+        for face in 0..FACES.len() {
+            for row in 0..size {
+                for col in 0..size {
+                    cells.push(CubeCell {
+                        face,
+                        row,
+                        col,
+                        links: HashSet::new(),
+                    });
+                }
+            }
+        }
+        CubeGrid { size, cells }
+    }
+
+    /// North, east, south and west of a cell, as directions in space.
+    fn directions(&self, ix: usize) -> [Vec3; 4] {
+        let (_, u, v, _) = FACES[self.cells[ix].face];
+        [v.map(|x| -x), u, v, u.map(|x| -x)]
+    }
+
+    /// The cell next to `ix` in direction `d`, folding over onto the next face at the edges.
+    fn step(&self, ix: usize, d: Vec3) -> usize {
+        let c = &self.cells[ix];
+        let size = self.size as i64;
+        let (o, u, v, n) = FACES[c.face];
+        let (col, row) = (c.col as i64 + dot(d, u), c.row as i64 + dot(d, v));
+        if (0..size).contains(&col) && (0..size).contains(&row) {
+            return (c.face * self.size + row as usize) * self.size + col as usize;
+        }
+
+        // Doubled coordinates keep cell centres on whole numbers. Half a cell on reaches
+        // the edge, and half a cell down the face beyond it is the neighbour's centre.
+        let centre: Vec3 = std::array::from_fn(|k| {
+            let here = (2 * c.col as i64 + 1) * u[k] + (2 * c.row as i64 + 1) * v[k];
+            2 * o[k] * size + here + d[k] - n[k]
+        });
+        let face = FACES.iter().position(|f| f.3 == d).unwrap();
+        let (o, u, v, _) = FACES[face];
+        let rel: Vec3 = std::array::from_fn(|k| centre[k] - 2 * o[k] * size);
+        let (col, row) = ((dot(rel, u) - 1) / 2, (dot(rel, v) - 1) / 2);
+        (face * self.size + row as usize) * self.size + col as usize
+    }
+
+    /// Point on the cube at `row`, `col` of a face, both counted in cells, in a model of
+    /// the cube centred on the origin.
+    fn point3(&self, face: usize, row: f64, col: f64) -> [f64; 3] {
+        let (o, u, v, _) = FACES[face];
+        let size = self.size as f64;
+        std::array::from_fn(|k| {
+            o[k] as f64 * size + col * u[k] as f64 + row * v[k] as f64 - size / 2.
+        })
+    }
+
+    fn is_visible(&self, face: usize) -> bool {
+        let n = FACES[face].3.map(|x| x as f64);
+        orthographic(n)[2] > 0.
+    }
+
+    /// Corners of a cell in the net and in the projection, clockwise from the north west.
+    fn corners(&self, ix: usize, cellsize: f64) -> (Quad, Quad) {
+        let c = &self.cells[ix];
+        let (fx, fy) = NET[c.face];
+        let offsets = [(0., 0.), (0., 1.), (1., 1.), (1., 0.)];
+        let net = offsets.map(|(dr, dc)| {
+            let x = (fx * self.size + c.col) as f64 + dc;
+            let y = (fy * self.size + c.row) as f64 + dr;
+            (x * cellsize, y * cellsize)
+        });
+        let projected = offsets.map(|(dr, dc)| {
+            self.project(
+                self.point3(c.face, c.row as f64 + dr, c.col as f64 + dc),
+                cellsize,
+            )
+        });
+        (net, projected)
+    }
+
+    fn centres(&self, ix: usize, cellsize: f64) -> ((f64, f64), [f64; 3]) {
+        let (x, y) = self.position(ix);
+        let c = &self.cells[ix];
+        let p = self.point3(c.face, c.row as f64 + 0.5, c.col as f64 + 0.5);
+        ((x * cellsize, y * cellsize), p)
+    }
+
+    /// The projection sits to the right of the net, in a square as tall as the net.
+    fn project(&self, p: [f64; 3], cellsize: f64) -> (f64, f64) {
+        let [x, y, _] = orthographic(p);
+        let size = self.size as f64;
+        ((5.5 * size + x) * cellsize, (1.5 * size + y) * cellsize)
+    }
+}
+
+impl CellPosition for CubeGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        let c = &self.cells[ix];
+        let (fx, fy) = NET[c.face];
+        (
+            (fx * self.size + c.col) as f64 + 0.5,
+            (fy * self.size + c.row) as f64 + 0.5,
+        )
+    }
+}
+
+impl AbstractGrid<CubeCell> for CubeGrid {
+    fn neighbours(&self, ix: usize) -> Vec<usize> {
+        self.directions(ix)
+            .iter()
+            .map(|&d| self.step(ix, d))
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn cell(&self, ix: usize) -> &CubeCell {
+        &self.cells[ix]
+    }
+
+    fn cell_mut(&mut self, ix: usize) -> &mut CubeCell {
+        &mut self.cells[ix]
+    }
+}
+
+impl GtkDrawable for CubeGrid {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        let size = self.size as f64;
+        (7. * size * cellsize, 3. * size * cellsize)
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        let side = match towards {
+            Move::North => 0,
+            Move::East => 1,
+            Move::South => 2,
+            Move::West => 3,
+            _ => return None,
+        };
+        let n = self.step(ix, self.directions(ix)[side]);
+        Some(n).filter(|n| self.cells[ix].links.contains(n))
+    }
+
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        let (net, projected) = self.corners(ix, cellsize);
+        polygon_path(cr, &net);
+        if self.is_visible(self.cells[ix].face) {
+            polygon_path(cr, &projected);
+        }
+    }
+
+    fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+        cr.set_line_width(1.0);
+
+        for ix in 0..self.len() {
+            let (net, projected) = self.corners(ix, cellsize);
+            let visible = self.is_visible(self.cells[ix].face);
+            for (side, n) in self.neighbours(ix).into_iter().enumerate() {
+                if self.cells[ix].links.contains(&n) {
+                    continue;
+                }
+                let next = (side + 1) % 4;
+                cr.move_to(net[side].0, net[side].1);
+                cr.line_to(net[next].0, net[next].1);
+                if visible {
+                    cr.move_to(projected[side].0, projected[side].1);
+                    cr.line_to(projected[next].0, projected[next].1);
+                }
+            }
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn draw_pathfind(
+        &self,
+        w: &DrawingArea,
+        cr: &Context,
+        step_state: &DijkstraStep,
+        cellsize: f64,
+    ) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let path = step_state.path_to(max_idx);
+
+        for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 {
+                continue;
+            }
+            let intensity = (max_length - c.path_length) as f64 / max_length as f64;
+            cr.set_source_rgb(intensity, 0.5 + intensity / 2., intensity);
+            let (net, projected) = self.corners(i, cellsize);
+            let mut polygons = vec![net];
+            if self.is_visible(self.cells[i].face) {
+                polygons.push(projected);
+            }
+            for corners in polygons {
+                cr.move_to(corners[0].0, corners[0].1);
+                for (x, y) in &corners[1..] {
+                    cr.line_to(*x, *y);
+                }
+                cr.close_path();
+                // overlap a little so no gaps show between neighbours
+                cr.fill_preserve().expect("error while drawing stroke");
+                cr.set_line_width(0.5);
+                cr.stroke().expect("error while drawing stroke");
+            }
+        }
+
+        cr.set_line_width(1.0);
+        cr.set_source_rgb(0., 0., 0.);
+        for ix in [path.first(), path.last()].into_iter().flatten() {
+            let ((x, y), _) = self.centres(*ix, cellsize);
+            cr.new_sub_path();
+            cr.arc(x, y, cellsize / 2., 0., 2. * PI);
+            cr.stroke().expect("error while drawing stroke");
+        }
+
+        cr.set_source_rgb(1., 0., 0.);
+        cr.set_line_width(4.0);
+        for pair in path.windows(2) {
+            let ((x1, y1), p1) = self.centres(pair[0], cellsize);
+            let ((x2, y2), p2) = self.centres(pair[1], cellsize);
+            // in the net, only where the two cells are drawn next to each other
+            if (x1 - x2).abs() + (y1 - y2).abs() <= cellsize * 1.01 {
+                cr.move_to(x1, y1);
+                cr.line_to(x2, y2);
+            }
+
+            // in the projection, through the middle of the wall between the cells
+            let (f1, f2) = (self.cells[pair[0]].face, self.cells[pair[1]].face);
+            let n1 = FACES[f1].3.map(|x| x as f64);
+            let edge: [f64; 3] = if f1 == f2 {
+                std::array::from_fn(|k| (p1[k] + p2[k]) / 2.)
+            } else {
+                std::array::from_fn(|k| p2[k] + n1[k] / 2.)
+            };
+            let edge = self.project(edge, cellsize);
+            for (p, face) in [(p1, f1), (p2, f2)] {
+                if self.is_visible(face) {
+                    let (x, y) = self.project(p, cellsize);
+                    cr.move_to(x, y);
+                    cr.line_to(edge.0, edge.1);
+                }
+            }
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    /// Row and column within the face under (x, y) in the net.
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let x = x * canvas_width / w.allocated_width() as f64;
+        let y = y * canvas_height / w.allocated_height() as f64;
+        if x < 0. || y < 0. {
+            return None;
+        }
+        let col = (x / cellsize).floor() as usize;
+        let row = (y / cellsize).floor() as usize;
+        NET.iter()
+            .position(|&f| f == (col / self.size, row / self.size))?;
+        Some((row % self.size, col % self.size))
+    }
+}
@@ -1,6 +1,8 @@
-use crate::draw_utils::GtkDrawable;
-use crate::grid::{AbstractCell, AbstractGrid, CompassDirections, RectangularGrid};
+use crate::draw_utils::{self, GtkDrawable};
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition, CompassDirections, RectangularGrid};
 use crate::gtk::prelude::WidgetExt;
+use crate::mask::Mask;
+use crate::play::{self, Move};
 use crate::rectangle::Cell;
 use crate::solve::DijkstraStep;
 use gtk::cairo::Context;
@@ -11,6 +13,7 @@ pub struct DeltaGrid {
     pub height: usize,
     pub width: usize,
     pub cells: Vec<Cell>,
+    pub mask: Mask,
 }
 
 impl CompassDirections for DeltaGrid {
@@ -50,6 +53,9 @@ impl CompassDirections for DeltaGrid {
 
 impl AbstractGrid<Cell> for DeltaGrid {
     fn neighbours(&self, ix: usize) -> Vec<usize> {
+        if !self.is_active(ix) {
+            return Vec::new();
+        }
         let neighbors = [
             self.north_ix(ix),
             self.south_ix(ix),
@@ -57,9 +63,17 @@ impl AbstractGrid<Cell> for DeltaGrid {
             self.west_ix(ix),
         ];
 
-        let neighbors: Vec<usize> = neighbors.iter().filter_map(|x| *x).collect();
+        let neighbors: Vec<usize> = neighbors
+            .iter()
+            .filter_map(|x| *x)
+            .filter(|&x| self.is_active(x))
+            .collect();
         neighbors
     }
+
+    fn is_active(&self, ix: usize) -> bool {
+        self.mask.is_on(self.cells[ix].row, self.cells[ix].col)
+    }
     fn len(&self) -> usize {
         self.cells.len()
     }
@@ -74,19 +88,39 @@ impl AbstractGrid<Cell> for DeltaGrid {
 }
 
 impl DeltaGrid {
+    #[allow(dead_code)]
     pub fn new(rows: usize, cols: usize) -> DeltaGrid {
+        DeltaGrid::masked(Mask::new(rows, cols))
+    }
+
+    pub fn masked(mask: Mask) -> DeltaGrid {
         let mut gridarr = Vec::new();
-        for i in 0..rows {
-            for j in 0..cols {
+        for i in 0..mask.rows {
+            for j in 0..mask.cols {
                 gridarr.push(Cell::new(i, j));
             }
         }
         DeltaGrid {
-            width: cols,
-            height: rows,
+            width: mask.cols,
+            height: mask.rows,
             cells: gridarr,
+            mask,
+        }
+    }
+}
+
+/// Mask for a large triangle pointing up, made of `size` rows of triangles.
+pub fn triangle_mask(size: usize) -> Mask {
+    // the apex has to be an upward triangle, so it may need to move one column over
+    let offset = (size + 1) % 2;
+    let apex = offset + size - 1;
+    let mut mask = Mask::new(size, apex + size);
+    for row in 0..size {
+        for col in 0..mask.cols {
+            mask.set(row, col, (apex - row..=apex + row).contains(&col));
         }
     }
+    mask
 }
 
 impl RectangularGrid for DeltaGrid {
@@ -98,7 +132,7 @@ impl RectangularGrid for DeltaGrid {
     }
 }
 
-fn is_up(row: usize, col: usize) -> bool {
+pub fn is_up(row: usize, col: usize) -> bool {
     (row + col) % 2 == 0
 }
 
@@ -139,18 +173,47 @@ fn delta_points(row: usize, col: usize, cellsize: f64) -> DeltaCellPoints {
     }
 }
 
+impl CellPosition for DeltaGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        let coords = delta_points(self.cells[ix].row, self.cells[ix].col, 1.);
+        (coords.cx, coords.cy)
+    }
+}
+
 impl GtkDrawable for DeltaGrid {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        (
+            (1 + self.width) as f64 * cellsize / 2. + cellsize * 0.1,
+            self.height as f64 * cellsize * 3f64.sqrt() / 2. + cellsize * 0.1,
+        )
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        play::compass_move(self, ix, towards)
+    }
+
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        let c = &self.cells[ix];
+        let p = delta_points(c.row, c.col, cellsize);
+        draw_utils::polygon_path(
+            cr,
+            &[(p.westx, p.basey), (p.midx, p.apexy), (p.eastx, p.basey)],
+        );
+    }
+
     fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
         cr.save().expect("error while saving coords");
 
-        let canvas_width = (1 + self.width) as f64 * (cellsize) / 2. + cellsize * 0.1;
-        let canvas_height = self.height as f64 * cellsize * 3f64.sqrt() / 2. + cellsize * 0.1;
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
 
         let scalex = w.allocated_width() as f64 / canvas_width;
         let scaley = w.allocated_height() as f64 / canvas_height;
         cr.scale(scalex, scaley);
 
         for ix in 0..self.len() {
+            if !self.is_active(ix) {
+                continue;
+            }
             let cur_cell = self.cell(ix);
             let draw_line = |item: &Option<usize>, end: (f64, f64)| match item {
                 Some(r_idx) if (!cur_cell.links().contains(r_idx)) => cr.line_to(end.0, end.1),
@@ -165,7 +228,11 @@ impl GtkDrawable for DeltaGrid {
             draw_line(&self.east_ix(ix), (coords.eastx, coords.basey));
 
             let isup = is_up(cur_cell.row(), cur_cell.col());
-            let no_south = isup && self.south_ix(ix).is_none();
+            let no_south = isup
+                && self
+                    .south_ix(ix)
+                    .map(|r_idx| !self.is_active(r_idx))
+                    .unwrap_or(true);
             let not_linked = !isup
                 && self
                     .north_ix(ix)
@@ -180,6 +247,37 @@ impl GtkDrawable for DeltaGrid {
         cr.restore().expect("error while restoring coords");
     }
 
+    fn draw_maze_inset(&self, w: &DrawingArea, cr: &Context, cellsize: f64, inset: f64) {
+        cr.save().expect("error while saving coords");
+
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+
+        for ix in (0..self.len()).filter(|&ix| self.is_active(ix)) {
+            let c = &self.cells[ix];
+            let coords = delta_points(c.row, c.col, cellsize);
+            let corners = [
+                (coords.westx, coords.basey),
+                (coords.midx, coords.apexy),
+                (coords.eastx, coords.basey),
+            ];
+            let base = if is_up(c.row, c.col) {
+                self.south_ix(ix)
+            } else {
+                self.north_ix(ix)
+            };
+            let open = [self.west_ix(ix), self.east_ix(ix), base]
+                .map(|n| n.is_some_and(|n| c.links.contains(&n)));
+            let centroid = (coords.cx, (2. * coords.basey + coords.apexy) / 3.);
+            draw_utils::draw_inset_polygon(cr, &corners, centroid, &open, inset);
+        }
+
+        cr.restore().expect("error while restoring coords");
+    }
+
     fn draw_pathfind(
         &self,
         w: &DrawingArea,
@@ -188,27 +286,15 @@ impl GtkDrawable for DeltaGrid {
         cellsize: f64,
     ) {
         cr.save().expect("error while saving coords");
-        let canvas_width = (1 + self.width) as f64 * (cellsize) / 2. + cellsize * 0.1;
-        let canvas_height = self.height as f64 * cellsize * 3f64.sqrt() / 2. + cellsize * 0.1;
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
 
         let scalex = w.allocated_width() as f64 / canvas_width;
         let scaley = w.allocated_height() as f64 / canvas_height;
         cr.scale(scalex, scaley);
 
-        let mut max_idx = 0;
-        let mut min_idx = 0;
-        let mut max_length = step_state.cell_weights[max_idx].path_length;
-        let mut min_length = max_length;
-        for (i, c) in step_state.cell_weights.iter().enumerate() {
-            if c.path_length > max_length {
-                max_length = c.path_length;
-                max_idx = i;
-            }
-            if c.path_length < min_length {
-                min_length = c.path_length;
-                min_idx = i;
-            }
-        }
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let min_idx = step_state.path_to(max_idx)[0];
 
         let coords = |ix: usize| {
             let row = self.cell(ix).row();
@@ -217,6 +303,9 @@ impl GtkDrawable for DeltaGrid {
         };
 
         for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 {
+                continue;
+            }
             let intensity = (max_length - c.path_length) as f64 / max_length as f64;
             let dark = intensity;
             let bright = 0.5 + intensity / 2.;
@@ -246,4 +335,40 @@ impl GtkDrawable for DeltaGrid {
 
         cr.restore().expect("error while restoring coords");
     }
+
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let x = x * canvas_width / w.allocated_width() as f64;
+        let y = y * canvas_height / w.allocated_height() as f64;
+        if x < 0. || y < 0. {
+            return None;
+        }
+
+        let row = (y / (cellsize * 3f64.sqrt() / 2.)).floor() as usize;
+        let col = (x / (cellsize / 2.)).floor() as usize;
+        // a point is inside a triangle when it is on the inner side of all three edges
+        let side = |p: (f64, f64), a: (f64, f64), b: (f64, f64)| {
+            (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+        };
+        for c in [col.wrapping_sub(1), col] {
+            if self.ix_opt(row, c).is_none() {
+                continue;
+            }
+            let pts = delta_points(row, c, cellsize);
+            let corners = [
+                (pts.westx, pts.basey),
+                (pts.midx, pts.apexy),
+                (pts.eastx, pts.basey),
+            ];
+            let sides = [
+                side((x, y), corners[0], corners[1]),
+                side((x, y), corners[1], corners[2]),
+                side((x, y), corners[2], corners[0]),
+            ];
+            if sides.iter().all(|&s| s >= 0.) || sides.iter().all(|&s| s <= 0.) {
+                return Some((row, c));
+            }
+        }
+        None
+    }
 }
@@ -0,0 +1,146 @@
+use crate::draw_utils::{make_tha_maze, GenerationType};
+use crate::grid::{AbstractCell, AbstractGrid};
+use crate::{solve, stats};
+use std::fmt::{Display, Error, Formatter};
+
+/// Weights of the length, decision and dead end parts of the score; they add up to 1.
+const LENGTH_WEIGHT: f64 = 0.3;
+const DECISION_WEIGHT: f64 = 0.3;
+const DEPTH_WEIGHT: f64 = 0.4;
+/// Most of the score loops can take away, as a share of it.
+const MAX_LOOP_PENALTY: f64 = 0.5;
+/// How much the braid chance moves between attempts that miss the band.
+const BRAID_STEP: u8 = 32;
+
+/// Score range asked for by `generate_within`, inclusive on both ends.
+#[derive(Clone, PartialEq, Debug)]
+pub struct DifficultyBand {
+    pub min: f64,
+    pub max: f64,
+}
+
+pub const EASY: DifficultyBand = DifficultyBand { min: 0., max: 35. };
+pub const MEDIUM: DifficultyBand = DifficultyBand { min: 35., max: 60. };
+pub const HARD: DifficultyBand = DifficultyBand {
+    min: 60.,
+    max: 100.,
+};
+
+impl DifficultyBand {
+    pub fn contains(&self, score: f64) -> bool {
+        self.min <= score && score <= self.max
+    }
+}
+
+impl Display for DifficultyBand {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(f, "{:.0}-{:.0}", self.min, self.max)
+    }
+}
+
+/// How hard a maze is to solve, from the solution `solve::solve_with_longest_path` draws.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Difficulty {
+    /// Steps on the solution.
+    pub solution: usize,
+    /// Cells on the solution with a passage leading off it.
+    pub decisions: usize,
+    /// Average steps from a dead end back to the solution.
+    pub dead_end_depth: f64,
+    /// Independent loops, 0 for a perfect maze.
+    pub loops: usize,
+    /// 0 for trivial mazes up to 100: long solutions with many decisions and deep dead end
+    /// branches score high, loops offering shortcuts bring the score down.
+    pub score: f64,
+}
+
+impl Difficulty {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"solution\":{},\"decisions\":{},\"dead_end_depth\":{:.4},\"loops\":{},\"score\":{:.2}}}",
+            self.solution, self.decisions, self.dead_end_depth, self.loops, self.score
+        )
+    }
+}
+
+/// Rates a generated maze. The parts of the score are scaled by the number of cells, so
+/// the same band suits any maze size.
+pub fn rate<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(g: &T) -> Difficulty {
+    let active: Vec<usize> = (0..g.len()).filter(|&ix| g.is_active(ix)).collect();
+    let step = solve::solve_with_longest_path(g);
+    let path = step.path_to(step.farthest());
+    let mut rating = Difficulty {
+        solution: path.len().saturating_sub(1),
+        ..Difficulty::default()
+    };
+
+    for (i, &ix) in path.iter().enumerate() {
+        let on_path = usize::from(i > 0) + usize::from(i + 1 < path.len());
+        if g.links(ix).len() > on_path {
+            rating.decisions += 1;
+        }
+    }
+    // steps to the nearest solution cell
+    let distance = solve::distances(g, &path);
+    let depths: Vec<usize> = active
+        .iter()
+        .filter(|&&ix| g.links(ix).len() == 1)
+        .filter_map(|&ix| distance[ix].filter(|&d| d > 0))
+        .collect();
+    if !depths.is_empty() {
+        rating.dead_end_depth = depths.iter().sum::<usize>() as f64 / depths.len() as f64;
+    }
+    rating.loops = stats::loops(g);
+
+    let cells = active.len().max(1) as f64;
+    let length = (2. * rating.solution as f64 / cells).min(1.);
+    let decisions = (2.5 * rating.decisions as f64 / rating.solution.max(1) as f64).min(1.);
+    let depth = (rating.dead_end_depth / cells.sqrt()).min(1.);
+    let penalty = (4. * rating.loops as f64 / cells).min(MAX_LOOP_PENALTY);
+    rating.score = 100.
+        * (LENGTH_WEIGHT * length + DECISION_WEIGHT * decisions + DEPTH_WEIGHT * depth)
+        * (1. - penalty);
+    rating
+}
+
+/// Generates mazes on grids from `make` until one rates within `band`, at most `attempts`
+/// times. Mazes that come out too hard are braided more on the next attempt and mazes that
+/// come out too easy less, starting from `braid_chance`; once there is no braiding left to
+/// take away, too easy mazes switch to the recursive backtracker, whose long winding
+/// solutions and deep dead ends score highest. Returns the maze closest to the band when
+/// none lands in it.
+pub fn generate_within<C: AbstractCell, T: AbstractGrid<C>>(
+    make: impl Fn() -> T,
+    generation_type: &GenerationType,
+    braid_chance: u8,
+    band: &DifficultyBand,
+    attempts: usize,
+) -> (T, Difficulty) {
+    let miss = |score: f64| (band.min - score).max(score - band.max).max(0.);
+    let mut braid = braid_chance;
+    let mut generation_type = generation_type.clone();
+    let mut best: Option<(T, Difficulty)> = None;
+    for _ in 0..attempts.max(1) {
+        let mut g = make();
+        make_tha_maze(&generation_type, &mut g, braid);
+        let rating = rate(&g);
+        if rating.score > band.max {
+            braid = braid.saturating_add(BRAID_STEP);
+        } else if rating.score < band.min && braid > 0 {
+            braid = braid.saturating_sub(BRAID_STEP);
+        } else if rating.score < band.min {
+            generation_type = GenerationType::RecursiveBacktracker;
+        }
+        let found = band.contains(rating.score);
+        if best
+            .as_ref()
+            .is_none_or(|(_, b)| miss(rating.score) < miss(b.score))
+        {
+            best = Some((g, rating));
+        }
+        if found {
+            break;
+        }
+    }
+    best.unwrap()
+}
@@ -1,13 +1,31 @@
 use gtk::glib::Propagation;
 
-use crate::grid::{AbstractCell, AbstractGrid};
+use crate::codec::{self, CodeError};
+use crate::generate::Progress;
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition};
 use crate::gtk::prelude::WidgetExt;
+use crate::mask::Mask;
+use crate::play::{Game, Move};
+use crate::puzzle::Puzzle;
 use crate::solve::DijkstraStep;
-use crate::{delta, generate, hexagonal, polar, rectangle, solve};
+use crate::stats::{self, MazeStats};
+use crate::{
+    cube, delta, generate, grid3d, hexagonal, polar, rectangle, solve, sphere, upsilon, weave,
+};
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::rc::Rc;
 use std::sync::{Arc, RwLock};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::Duration;
 
-pub trait GtkDrawable {
+pub const CELL_SIZE: f64 = 10.;
+
+/// Grids are built on a worker thread and drawn on the GTK one, so they must be `Send + Sync`.
+pub trait GtkDrawable: CellPosition + Send + Sync {
+    /// Size of the drawing at `cellsize`. The drawing methods stretch it over the whole
+    /// widget, `Settings::render` corrects the aspect ratio.
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64);
     fn draw_pathfind(
         &self,
         w: &gtk::DrawingArea,
@@ -15,15 +33,138 @@ pub trait GtkDrawable {
         step_state: &DijkstraStep,
         cellsize: f64,
     );
+    /// Path finding drawn under `draw_maze_inset`, for grids whose distances fill only
+    /// the passages.
+    fn draw_pathfind_inset(
+        &self,
+        w: &gtk::DrawingArea,
+        cr: &gtk::cairo::Context,
+        step_state: &DijkstraStep,
+        cellsize: f64,
+        _inset: f64,
+    ) {
+        self.draw_pathfind(w, cr, step_state, cellsize)
+    }
     fn draw_maze(&self, w: &gtk::DrawingArea, cr: &gtk::cairo::Context, cellsize: f64);
+    /// Draws passages narrower than the cells; `inset` is the part of a cell's width given
+    /// to walls, from 0 (plain `draw_maze`) to 1. Grids without an inset drawing ignore it.
+    fn draw_maze_inset(
+        &self,
+        w: &gtk::DrawingArea,
+        cr: &gtk::cairo::Context,
+        cellsize: f64,
+        _inset: f64,
+    ) {
+        self.draw_maze(w, cr, cellsize)
+    }
+    /// Row and column of the cell under widget coordinates (x, y), if any.
+    fn cell_at(
+        &self,
+        w: &gtk::DrawingArea,
+        x: f64,
+        y: f64,
+        cellsize: f64,
+    ) -> Option<(usize, usize)>;
+    /// Middle of a cell in the unstretched drawing, see `canvas_size`.
+    fn cell_centre(&self, ix: usize, cellsize: f64) -> (f64, f64) {
+        let (x, y) = self.position(ix);
+        (x * cellsize, y * cellsize)
+    }
+    /// Cell a passage leads to from `ix` in the direction of `towards`, if any.
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize>;
+    /// Cell that move passes through on the way, such as the one beneath a weave crossing.
+    fn passed_through(&self, _ix: usize, _towards: Move) -> Option<usize> {
+        None
+    }
+    /// Adds the outline of a cell to the current path, in the unstretched drawing. Cells
+    /// drawn twice, in a net and in a projection, add both outlines.
+    fn cell_path(&self, cr: &gtk::cairo::Context, ix: usize, cellsize: f64);
 }
 
-#[derive(Clone, PartialEq, Eq, Debug)]
+/// Turns a point of a 3D model (x right, y up, z towards the viewer) so it is seen from
+/// above and to the right, for the orthographic views of surface mazes. Returns screen x,
+/// screen y (pointing down) and depth, which is positive on the side facing the viewer.
+pub fn orthographic(p: [f64; 3]) -> [f64; 3] {
+    let (yaw, pitch) = (-PI / 6., PI / 7.);
+    let x = p[0] * yaw.cos() + p[2] * yaw.sin();
+    let z = -p[0] * yaw.sin() + p[2] * yaw.cos();
+    let y = p[1] * pitch.cos() - z * pitch.sin();
+    [x, -y, p[1] * pitch.sin() + z * pitch.cos()]
+}
+
+/// Adds a closed polygon through `corners` to the current path.
+pub fn polygon_path(cr: &gtk::cairo::Context, corners: &[(f64, f64)]) {
+    cr.move_to(corners[0].0, corners[0].1);
+    for (x, y) in &corners[1..] {
+        cr.line_to(*x, *y);
+    }
+    cr.close_path();
+}
+
+/// Draws the walls of a cell pulled `inset` of the way from its corners towards `centre`,
+/// with corridors out to the sides marked `open`. Side i runs from corner i to corner i + 1.
+pub fn draw_inset_polygon(
+    cr: &gtk::cairo::Context,
+    corners: &[(f64, f64)],
+    centre: (f64, f64),
+    open: &[bool],
+    inset: f64,
+) {
+    let lerp =
+        |a: (f64, f64), b: (f64, f64), t: f64| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+    // foot of the perpendicular from p to the line through a and b
+    let project = |p: (f64, f64), a: (f64, f64), b: (f64, f64)| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let t = ((p.0 - a.0) * dx + (p.1 - a.1) * dy) / (dx * dx + dy * dy);
+        lerp(a, b, t)
+    };
+    let inner: Vec<(f64, f64)> = corners.iter().map(|&c| lerp(c, centre, inset)).collect();
+    for i in 0..corners.len() {
+        let j = (i + 1) % corners.len();
+        if open[i] {
+            for k in [i, j] {
+                let side = project(inner[k], corners[i], corners[j]);
+                cr.move_to(inner[k].0, inner[k].1);
+                cr.line_to(side.0, side.1);
+            }
+        } else {
+            cr.move_to(inner[i].0, inner[i].1);
+            cr.line_to(inner[j].0, inner[j].1);
+        }
+    }
+    cr.stroke().expect("error while drawing stroke");
+}
+
+#[derive(Clone, PartialEq, Eq, Debug, Hash)]
 pub enum MazeType {
     Regular,
     Circular,
     Hexagonal,
     Delta,
+    Weave,
+    ThreeD,
+    Cube,
+    Sphere,
+    Upsilon,
+}
+
+impl MazeType {
+    /// Whether cells of this grid can be disabled by a mask.
+    pub fn maskable(&self) -> bool {
+        !matches!(
+            self,
+            MazeType::Circular
+                | MazeType::Weave
+                | MazeType::ThreeD
+                | MazeType::Cube
+                | MazeType::Sphere
+        )
+    }
+
+    /// Whether the grid has north, east, south and west, as `GenerationType::needs_compass`.
+    pub fn has_compass(&self) -> bool {
+        matches!(self, MazeType::Regular | MazeType::Weave)
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Debug)]
@@ -32,119 +173,826 @@ pub enum GenerationType {
     AldousBroder,
     SimplifiedPrim,
     TruePrim,
+    Kruskal,
+    BinaryTree,
+    Sidewinder,
+}
+
+impl GenerationType {
+    /// Binary tree and sidewinder only carve north and east, so they only run on grids
+    /// with compass directions, see `MazeType::has_compass`.
+    pub fn needs_compass(&self) -> bool {
+        matches!(self, GenerationType::BinaryTree | GenerationType::Sidewinder)
+    }
 }
 
 pub struct Settings {
     pub maze_type: MazeType,
     pub generation_type: GenerationType,
     pub braid_chance: u8,
-    pub version: u128,
+    /// Bumped by `invalidate` whenever the drawing changes.
+    pub version: u64,
     pub step: DijkstraStep,
-    pub drawn: bool,
     pub grid: Box<dyn GtkDrawable>,
+    pub code: String,
+    /// Shape painted in the mask editor, used for rectangle, hex, delta and upsilon mazes.
+    pub mask: Option<Mask>,
+    pub editing_mask: bool,
+    /// Part of the cell width given to walls, see `GtkDrawable::draw_maze_inset`.
+    pub inset: f64,
+    /// Border wrapping for rectangle mazes.
+    pub wrap: rectangle::Wrap,
+    /// Draw copies of a wrapped maze around it to show how the edges join.
+    pub tiled: bool,
+    /// Ring layout of polar mazes.
+    pub polar: polar::PolarOptions,
+    /// Size picked for each maze type, see `size`.
+    pub sizes: HashMap<MazeType, (usize, usize)>,
+    /// Progress of the latest generator run; cancelled when a newer one starts.
+    pub progress: Arc<Progress>,
+    pub view: View,
+    /// The walk through the maze while it is being played; hides the solution.
+    pub game: Option<Game>,
+    /// Play in fog-of-war mode, see `play::Fog`.
+    pub fog: bool,
+    /// Number of locked doors on the solution of new mazes.
+    pub doors: usize,
+    pub puzzle: Option<Puzzle>,
+    /// Statistics of the current maze, None while the mask is edited.
+    pub stats: Option<MazeStats>,
+}
+
+/// Everything a maze is generated from, copied out of `Settings` so generation can run on
+/// another thread without holding them.
+#[derive(Clone)]
+pub struct MazeRequest {
+    pub maze_type: MazeType,
+    pub generation_type: GenerationType,
+    pub braid_chance: u8,
+    /// Painted mask of maskable maze types, used instead of `size`.
+    pub mask: Option<Mask>,
+    pub size: (usize, usize),
+    pub wrap: rectangle::Wrap,
+    pub polar: polar::PolarOptions,
+    /// Locked doors to put on the solution, see `Puzzle::place`.
+    pub doors: usize,
 }
 
-unsafe impl Send for Settings {}
-unsafe impl Sync for Settings {}
+/// A generated and solved maze, see `Settings::install_maze`.
+pub struct Maze {
+    pub grid: Box<dyn GtkDrawable>,
+    pub step: DijkstraStep,
+    pub code: String,
+    pub puzzle: Option<Puzzle>,
+    pub stats: MazeStats,
+}
 
 pub fn make_tha_maze<C: AbstractCell, T: AbstractGrid<C>>(
     generation_type: &GenerationType,
     grid: &mut T,
     braid_chance: u8,
+) {
+    make_tha_maze_with_progress(generation_type, grid, braid_chance, &Progress::default())
+}
+
+/// `make_tha_maze` reporting the cells carved to `progress`, and giving up once it is
+/// cancelled. Panics for generators that need compass directions the grid does not have;
+/// callers check `GenerationType::needs_compass` first.
+pub fn make_tha_maze_with_progress<C: AbstractCell, T: AbstractGrid<C>>(
+    generation_type: &GenerationType,
+    grid: &mut T,
+    braid_chance: u8,
+    progress: &Progress,
 ) {
     let mut rng = rand::rng();
-    match generation_type {
-        GenerationType::RecursiveBacktracker => generate::recursive_backtracker(grid, &mut rng),
-        GenerationType::AldousBroder => generate::aldous_broder(grid, &mut rng),
-        GenerationType::SimplifiedPrim => generate::simplified_prim(grid, &mut rng),
-        GenerationType::TruePrim => generate::true_prim(grid, &mut rng),
+    progress.start((0..grid.len()).filter(|&ix| grid.is_active(ix)).count());
+    // Masked grids can fall apart into several regions, each needs its own run.
+    loop {
+        match generation_type {
+            GenerationType::RecursiveBacktracker => {
+                generate::recursive_backtracker(grid, &mut rng, progress)
+            }
+            GenerationType::AldousBroder => generate::aldous_broder(grid, &mut rng, progress),
+            GenerationType::SimplifiedPrim => generate::simplified_prim(grid, &mut rng, progress),
+            GenerationType::TruePrim => generate::true_prim(grid, &mut rng, progress),
+            GenerationType::Kruskal => generate::kruskal(grid, &mut rng, progress),
+            GenerationType::BinaryTree => match grid.as_compass() {
+                Some(g) => {
+                    generate::binary_tree(g, &mut rng, progress);
+                    // joins the parts a mask cuts off from the north and east borders
+                    generate::kruskal(g, &mut rng, progress)
+                }
+                None => panic!("binary tree needs a grid with compass directions"),
+            },
+            GenerationType::Sidewinder => match grid.as_compass() {
+                Some(g) => {
+                    generate::sidewinder(g, &mut rng, progress);
+                    generate::kruskal(g, &mut rng, progress)
+                }
+                None => panic!("sidewinder needs a grid with compass directions"),
+            },
+        }
+        if progress.is_cancelled() {
+            return;
+        }
+        if generate::first_unfilled(grid).is_none() {
+            break;
+        }
     }
 
     if braid_chance > 0 {
         generate::braid(grid, &mut rng, braid_chance)
     }
 }
+/// Grid size for each maze type when no mask is set; polar and sphere grids only use the
+/// rows, cubes use the rows for the size of a face.
+pub fn default_size(maze_type: &MazeType) -> (usize, usize) {
+    match maze_type {
+        MazeType::Regular => (70, 70),
+        MazeType::Circular => (40, 0),
+        MazeType::Hexagonal => (50, 50),
+        MazeType::Delta => (45, 70),
+        MazeType::Weave => (40, 40),
+        MazeType::ThreeD => (20, 20),
+        MazeType::Cube => (15, 15),
+        MazeType::Sphere => (15, 0),
+        MazeType::Upsilon => (35, 35),
+    }
+}
+
+/// Number of floors in 3D mazes.
+pub const LEVELS: usize = 3;
+
+/// Smallest and largest rows or columns the size can be set to.
+pub const MIN_SIZE: usize = 2;
+pub const MAX_SIZE: usize = 300;
+
+impl Default for Settings {
+    fn default() -> Self {
+        let (rows, cols) = default_size(&MazeType::Regular);
+        let grid = rectangle::RegularGrid::new(rows, cols);
+        Settings {
+            maze_type: MazeType::Regular,
+            generation_type: GenerationType::RecursiveBacktracker,
+            braid_chance: 0,
+            version: 0,
+            step: DijkstraStep::initial(&grid, 0),
+            grid: Box::new(grid),
+            code: String::new(),
+            mask: None,
+            editing_mask: false,
+            inset: 0.,
+            wrap: rectangle::Wrap::None,
+            tiled: false,
+            polar: polar::PolarOptions::default(),
+            sizes: HashMap::new(),
+            progress: Arc::new(Progress::default()),
+            view: View::default(),
+            game: None,
+            fog: false,
+            doors: 0,
+            puzzle: None,
+            stats: None,
+        }
+    }
+}
+
 impl Settings {
-    pub fn make_maze(&mut self) {
-        self.grid = match self.maze_type {
+    /// Grid size of the current maze type when no mask is set, `default_size` until it is
+    /// changed with `resize`.
+    pub fn size(&self) -> (usize, usize) {
+        match self.sizes.get(&self.maze_type) {
+            Some(size) => *size,
+            None => default_size(&self.maze_type),
+        }
+    }
+
+    /// Changes the size of the current maze type, clamped to `MIN_SIZE..=MAX_SIZE`.
+    /// Returns false if it already had that size.
+    pub fn resize(&mut self, rows: usize, cols: usize) -> bool {
+        let mut size = (
+            rows.clamp(MIN_SIZE, MAX_SIZE),
+            cols.clamp(MIN_SIZE, MAX_SIZE),
+        );
+        if !self.has_columns() {
+            size.1 = default_size(&self.maze_type).1;
+        }
+        if self.size() == size {
+            return false;
+        }
+        self.sizes.insert(self.maze_type.clone(), size);
+        true
+    }
+
+    /// Polar and sphere mazes only have rings, cubes a single edge length.
+    pub fn has_columns(&self) -> bool {
+        !matches!(
+            self.maze_type,
+            MazeType::Circular | MazeType::Cube | MazeType::Sphere
+        )
+    }
+
+    /// Snapshot of the settings a new maze is generated from.
+    pub fn request(&self) -> MazeRequest {
+        MazeRequest {
+            maze_type: self.maze_type.clone(),
+            generation_type: self.generation_type.clone(),
+            braid_chance: self.braid_chance,
+            mask: self.mask.clone().filter(|_| self.maskable()),
+            size: self.size(),
+            wrap: self.wrap.clone(),
+            polar: self.polar.clone(),
+            doors: self.doors,
+        }
+    }
+
+    /// Replaces the current maze with the one described by a code from `codec::encode`.
+    pub fn load_code(&mut self, code: &str) -> Result<(), CodeError> {
+        let decoded = codec::decode(code)?;
+        let (rows, cols) = (decoded.rows, decoded.cols);
+        let masked = decoded.mask.is_some();
+        let mask = decoded
+            .mask
+            .clone()
+            .unwrap_or_else(|| Mask::new(rows, cols));
+        match decoded.maze_type {
             MazeType::Regular => {
-                let mut g1 = Box::new(rectangle::RegularGrid::new(70, 70));
+                let mut g1 = Box::new(rectangle::RegularGrid::masked(mask));
+                g1.wrap = decoded.wrap.clone();
+                decoded.apply(&mut *g1)?;
+                self.maze_type = decoded.maze_type;
+                self.mask = decoded.mask;
+                self.wrap = decoded.wrap;
+                self.install(g1, rows, cols);
+            }
+            MazeType::Circular => {
+                let mut g1 = Box::new(polar::CircularGrid::new(rows));
+                decoded.apply(&mut *g1)?;
+                self.maze_type = decoded.maze_type;
+                self.polar = polar::PolarOptions::default();
+                self.install(g1, rows, cols);
+            }
+            MazeType::Hexagonal => {
+                let mut g1 = Box::new(hexagonal::HexagonalGrid::masked(mask));
+                decoded.apply(&mut *g1)?;
+                self.maze_type = decoded.maze_type;
+                self.mask = decoded.mask;
+                self.install(g1, rows, cols);
+            }
+            MazeType::Delta => {
+                let mut g1 = Box::new(delta::DeltaGrid::masked(mask));
+                decoded.apply(&mut *g1)?;
+                self.maze_type = decoded.maze_type;
+                self.mask = decoded.mask;
+                self.install(g1, rows, cols);
+            }
+            MazeType::Upsilon => {
+                let mut g1 = Box::new(upsilon::UpsilonGrid::masked(mask));
+                decoded.apply(&mut *g1)?;
+                self.maze_type = decoded.maze_type;
+                self.mask = decoded.mask;
+                self.install(g1, rows, cols);
+            }
+            MazeType::Cube => {
+                let mut g1 = Box::new(cube::CubeGrid::new(rows));
+                decoded.apply(&mut *g1)?;
+                self.maze_type = decoded.maze_type;
+                self.install(g1, rows, rows);
+            }
+            MazeType::Sphere => {
+                let mut g1 = Box::new(sphere::SphereGrid::new(rows));
+                decoded.apply(&mut *g1)?;
+                self.maze_type = decoded.maze_type;
+                self.install(g1, rows, 0);
+            }
+            MazeType::Weave | MazeType::ThreeD => {
+                let id = codec::maze_type_id(&decoded.maze_type);
+                return Err(CodeError::UnknownMazeType(id));
+            }
+        };
+        if !masked {
+            self.resize(rows, cols);
+        }
+        Ok(())
+    }
 
-                make_tha_maze(&self.generation_type, &mut *g1, self.braid_chance);
-                let step_state = solve::solve_with_longest_path(&*g1);
-                self.step = step_state;
-                g1
+    /// The painted mask, or a mask with every cell enabled at the chosen size. Maze types
+    /// that can not be masked always get the chosen size.
+    fn grid_mask(&self) -> Mask {
+        match &self.mask {
+            Some(mask) if self.maskable() => mask.clone(),
+            _ => {
+                let (rows, cols) = self.size();
+                Mask::new(rows, cols)
+            }
+        }
+    }
+
+    /// Built-in boundary for the maze type at its chosen size: a hexagon of hexes or a
+    /// triangle of triangles.
+    pub fn shape_mask(&self) -> Option<Mask> {
+        let (rows, _) = self.size();
+        match self.maze_type {
+            MazeType::Hexagonal => Some(hexagonal::hexagon_mask(rows)),
+            MazeType::Delta => Some(delta::triangle_mask(rows)),
+            _ => None,
+        }
+    }
+
+    fn maskable(&self) -> bool {
+        self.maze_type.maskable()
+    }
+
+    /// Replaces the maze with an empty grid showing the mask, for painting.
+    pub fn show_mask(&mut self) {
+        self.invalidate();
+        self.game = None;
+        self.puzzle = None;
+        self.stats = None;
+        let mask = self.grid_mask();
+        match self.maze_type {
+            MazeType::Regular => {
+                let mut g1 = Box::new(rectangle::RegularGrid::masked(mask));
+                g1.wrap = self.wrap.clone();
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
             }
             MazeType::Circular => {
-                let mut g1 = Box::new(polar::CircularGrid::new(40));
-                make_tha_maze(&self.generation_type, &mut *g1, self.braid_chance);
-                let step_state = solve::solve_with_longest_path(&*g1);
-                self.step = step_state;
-                g1
+                let g1 = Box::new(polar::CircularGrid::with_options(mask.rows, &self.polar));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
+            }
+            MazeType::Weave => {
+                let g1 = Box::new(weave::WeaveGrid::new(mask.rows, mask.cols));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
+            }
+            MazeType::ThreeD => {
+                let g1 = Box::new(grid3d::Grid3D::new(LEVELS, mask.rows, mask.cols));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
+            }
+            MazeType::Cube => {
+                let g1 = Box::new(cube::CubeGrid::new(mask.rows));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
+            }
+            MazeType::Sphere => {
+                let g1 = Box::new(sphere::SphereGrid::new(mask.rows));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
             }
             MazeType::Hexagonal => {
-                let mut g1 = Box::new(hexagonal::HexagonalGrid::new(50, 50));
+                let g1 = Box::new(hexagonal::HexagonalGrid::masked(mask));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
+            }
+            MazeType::Delta => {
+                let g1 = Box::new(delta::DeltaGrid::masked(mask));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
+            }
+            MazeType::Upsilon => {
+                let g1 = Box::new(upsilon::UpsilonGrid::masked(mask));
+                self.step = solve::solve_with_longest_path(&*g1);
+                self.grid = g1;
+            }
+        }
+    }
+
+    /// Enables or disables the cell under widget coordinates (x, y). Returns the new state
+    /// of the cell, or None if there is no cell there or the maze type can not be masked.
+    pub fn paint_mask(
+        &mut self,
+        w: &gtk::DrawingArea,
+        x: f64,
+        y: f64,
+        on: Option<bool>,
+    ) -> Option<bool> {
+        if !self.maskable() {
+            return None;
+        }
+        let (x, y) = self.grid_point(w, x, y);
+        let (row, col) = self.grid.cell_at(w, x, y, CELL_SIZE)?;
+        let mut mask = self.grid_mask();
+        let on = on.unwrap_or(!mask.is_on(row, col));
+        if mask.is_on(row, col) != on {
+            mask.set(row, col, on);
+            self.mask = Some(mask);
+            self.show_mask();
+        }
+        Some(on)
+    }
+
+    /// How many copies of the maze the tiled view draws across and down. The fog only
+    /// covers one copy, so the others are left out while it is up.
+    fn tile_copies(&self) -> (usize, usize) {
+        let fogged = self
+            .game
+            .as_ref()
+            .is_some_and(|game| game.fog.is_some() && !game.is_won());
+        if !self.tiled || self.editing_mask || fogged || self.maze_type != MazeType::Regular {
+            return (1, 1);
+        }
+        match self.wrap {
+            rectangle::Wrap::None => (1, 1),
+            rectangle::Wrap::Cylinder | rectangle::Wrap::Moebius => (3, 1),
+            rectangle::Wrap::Torus => (3, 3),
+        }
+    }
+
+    fn install<C: AbstractCell, T: AbstractGrid<C> + GtkDrawable + 'static>(
+        &mut self,
+        g: Box<T>,
+        rows: usize,
+        cols: usize,
+    ) {
+        let maze = self.request().finish(g, rows, cols);
+        self.install_maze(maze);
+    }
+
+    /// Shows a maze made by `MazeRequest::generate`.
+    pub fn install_maze(&mut self, maze: Maze) {
+        self.grid = maze.grid;
+        self.step = maze.step;
+        self.code = maze.code;
+        self.puzzle = maze.puzzle;
+        self.stats = Some(maze.stats);
+        if self.game.is_some() {
+            self.game = Some(self.new_game());
+        }
+        self.invalidate();
+    }
+
+    /// Starts playing the current maze, or stops.
+    pub fn set_playing(&mut self, playing: bool) {
+        self.game = playing.then(|| self.new_game());
+        self.invalidate();
+    }
+
+    /// Turns fog-of-war on or off, also for a game being played.
+    pub fn set_fog(&mut self, fog: bool) {
+        self.fog = fog;
+        if let Some(game) = &mut self.game {
+            game.set_fog(&*self.grid, fog);
+        }
+    }
+
+    fn new_game(&self) -> Game {
+        let mut game = Game::new(&self.step, self.puzzle.clone());
+        game.set_fog(&*self.grid, self.fog);
+        game
+    }
+
+    /// Marks the drawing out of date, so the next draw renders the maze again instead of
+    /// using the cached surface. Needed after any change to what `render` shows.
+    pub fn invalidate(&mut self) {
+        self.version += 1;
+    }
 
-                make_tha_maze(&self.generation_type, &mut *g1, self.braid_chance);
-                let step_state = solve::solve_with_longest_path(&*g1);
-                self.step = step_state;
-                g1
+    /// Draws the maze, with its solution unless the mask is being edited, and the tiled
+    /// copies around it.
+    pub fn render(&self, w: &gtk::DrawingArea, cr: &gtk::cairo::Context) {
+        let (across, down) = self.tile_copies();
+        let width = w.allocated_width() as f64;
+        let height = w.allocated_height() as f64;
+        // the grids stretch their drawing over the widget, undo that for the placement
+        let placement = self.placement(width, height);
+        let (canvas_width, canvas_height) = self.canvas_size();
+        cr.save().expect("error while saving coords");
+        cr.translate(placement.x, placement.y);
+        cr.scale(
+            placement.scale * canvas_width / width,
+            placement.scale * canvas_height / height,
+        );
+        for i in 0..across {
+            for j in 0..down {
+                cr.save().expect("error while saving coords");
+                cr.translate(
+                    i as f64 * width / across as f64,
+                    j as f64 * height / down as f64,
+                );
+                cr.scale(1. / across as f64, 1. / down as f64);
+                // neighbouring copies of a Moebius strip are upside down
+                if self.wrap == rectangle::Wrap::Moebius && i % 2 != (across / 2) % 2 {
+                    cr.translate(0., height);
+                    cr.scale(1., -1.);
+                }
+                if !self.editing_mask && self.game.is_none() {
+                    if self.inset > 0. {
+                        self.grid.draw_pathfind_inset(w, cr, &self.step, CELL_SIZE, self.inset);
+                    } else {
+                        self.grid.draw_pathfind(w, cr, &self.step, CELL_SIZE);
+                    }
+                }
+                if self.inset > 0. {
+                    self.grid.draw_maze_inset(w, cr, CELL_SIZE, self.inset);
+                } else {
+                    self.grid.draw_maze(w, cr, CELL_SIZE);
+                }
+                // games draw the doors themselves, leaving out the open ones
+                if let Some(puzzle) = &self.puzzle
+                    && self.game.is_none()
+                {
+                    let (grid_width, grid_height) = self.grid.canvas_size(CELL_SIZE);
+                    cr.scale(width / grid_width, height / grid_height);
+                    puzzle.draw_solution(&*self.grid, cr, CELL_SIZE);
+                    puzzle.draw(&*self.grid, cr, CELL_SIZE, 0);
+                }
+                cr.restore().expect("error while restoring coords");
+            }
+        }
+        cr.restore().expect("error while restoring coords");
+    }
+
+    /// Draws the game over the maze in the middle tile, and the win banner.
+    pub fn draw_game(&self, w: &gtk::DrawingArea, cr: &gtk::cairo::Context) {
+        let Some(game) = &self.game else {
+            return;
+        };
+        let width = w.allocated_width() as f64;
+        let height = w.allocated_height() as f64;
+        let placement = self.placement(width, height);
+        let (across, down) = self.tile_copies();
+        let (grid_width, grid_height) = self.grid.canvas_size(CELL_SIZE);
+        cr.save().expect("error while saving coords");
+        cr.translate(placement.x, placement.y);
+        cr.scale(placement.scale, placement.scale);
+        cr.translate(
+            (across / 2) as f64 * grid_width,
+            (down / 2) as f64 * grid_height,
+        );
+        game.draw(&*self.grid, cr, CELL_SIZE);
+        cr.restore().expect("error while restoring coords");
+        game.draw_win(cr, width, height);
+    }
+
+    /// Size of the drawing with all tiled copies.
+    fn canvas_size(&self) -> (f64, f64) {
+        let (across, down) = self.tile_copies();
+        let (width, height) = self.grid.canvas_size(CELL_SIZE);
+        (width * across as f64, height * down as f64)
+    }
+
+    /// Where the drawing goes in a widget of the given size: fitted without distortion and
+    /// centred, then zoomed around the centre of the widget and panned.
+    pub fn placement(&self, width: f64, height: f64) -> Placement {
+        let (canvas_width, canvas_height) = self.canvas_size();
+        let fit = (width / canvas_width).min(height / canvas_height);
+        let (x, y) = (
+            (width - canvas_width * fit) / 2.,
+            (height - canvas_height * fit) / 2.,
+        );
+        let (cx, cy) = (width / 2., height / 2.);
+        let zoom = self.view.zoom;
+        Placement {
+            scale: fit * zoom,
+            x: cx + zoom * (x - cx) + self.view.pan.0,
+            y: cy + zoom * (y - cy) + self.view.pan.1,
+        }
+    }
+
+    /// Turns widget coordinates into the stretched ones `GtkDrawable::cell_at` expects.
+    pub fn grid_point(&self, w: &gtk::DrawingArea, x: f64, y: f64) -> (f64, f64) {
+        let width = w.allocated_width() as f64;
+        let height = w.allocated_height() as f64;
+        let placement = self.placement(width, height);
+        let (canvas_width, canvas_height) = self.canvas_size();
+        (
+            (x - placement.x) / (placement.scale * canvas_width / width),
+            (y - placement.y) / (placement.scale * canvas_height / height),
+        )
+    }
+
+    /// Zooms in (`factor` above 1) or out, keeping the point (x, y) of a widget of the given
+    /// size in place.
+    pub fn zoom_at(&mut self, factor: f64, x: f64, y: f64, width: f64, height: f64) {
+        let old = self.view.zoom;
+        let zoom = (old * factor).clamp(MIN_ZOOM, MAX_ZOOM);
+        let (cx, cy) = (width / 2., height / 2.);
+        let (px, py) = self.view.pan;
+        self.view.pan = (
+            x - cx - zoom * (x - cx - px) / old,
+            y - cy - zoom * (y - cy - py) / old,
+        );
+        self.view.zoom = zoom;
+    }
+}
+
+/// Zoom and pan of the maze view; the default shows the whole maze.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct View {
+    pub zoom: f64,
+    /// Offset of the drawing in widget pixels.
+    pub pan: (f64, f64),
+}
+
+impl Default for View {
+    fn default() -> Self {
+        View {
+            zoom: 1.,
+            pan: (0., 0.),
+        }
+    }
+}
+
+pub const MIN_ZOOM: f64 = 0.25;
+pub const MAX_ZOOM: f64 = 64.;
+
+/// Scale and offset taking drawing coordinates to widget coordinates, see
+/// `Settings::placement`.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Placement {
+    pub scale: f64,
+    pub x: f64,
+    pub y: f64,
+}
+
+impl MazeRequest {
+    fn grid_mask(&self) -> Mask {
+        match &self.mask {
+            Some(mask) => mask.clone(),
+            None => Mask::new(self.size.0, self.size.1),
+        }
+    }
+
+    /// Generates and solves the maze, or gives up with None once `progress` is cancelled.
+    pub fn generate(&self, progress: &Progress) -> Option<Maze> {
+        let mask = self.grid_mask();
+        let (rows, cols) = (mask.rows, mask.cols);
+        let maze = match self.maze_type {
+            MazeType::Regular => {
+                let mut g1 = Box::new(rectangle::RegularGrid::masked(mask));
+                g1.wrap = self.wrap.clone();
+                self.build(g1, rows, cols, progress)
+            }
+            MazeType::Circular => {
+                let g1 = Box::new(polar::CircularGrid::with_options(rows, &self.polar));
+                self.build(g1, rows, 0, progress)
+            }
+            MazeType::Weave => {
+                let mut g1 = Box::new(weave::WeaveGrid::new(rows, cols));
+                if self.generation_type == GenerationType::Kruskal {
+                    g1.add_crossings(&mut rand::rng(), rows * cols);
+                }
+                self.build(g1, rows, cols, progress)
+            }
+            MazeType::ThreeD => {
+                let g1 = Box::new(grid3d::Grid3D::new(LEVELS, rows, cols));
+                self.build(g1, rows, cols, progress)
+            }
+            MazeType::Cube => {
+                let g1 = Box::new(cube::CubeGrid::new(rows));
+                self.build(g1, rows, rows, progress)
+            }
+            MazeType::Sphere => {
+                let g1 = Box::new(sphere::SphereGrid::new(rows));
+                self.build(g1, rows, 0, progress)
+            }
+            MazeType::Hexagonal => {
+                let g1 = Box::new(hexagonal::HexagonalGrid::masked(mask));
+                self.build(g1, rows, cols, progress)
             }
             MazeType::Delta => {
-                let mut g1 = Box::new(delta::DeltaGrid::new(45, 70));
-                make_tha_maze(&self.generation_type, &mut *g1, self.braid_chance);
-                let step_state = solve::solve_with_longest_path(&*g1);
-                self.step = step_state;
-                g1
+                let g1 = Box::new(delta::DeltaGrid::masked(mask));
+                self.build(g1, rows, cols, progress)
+            }
+            MazeType::Upsilon => {
+                let g1 = Box::new(upsilon::UpsilonGrid::masked(mask));
+                self.build(g1, rows, cols, progress)
             }
         };
-        let start = SystemTime::now();
-        let since_the_epoch = start
-            .duration_since(UNIX_EPOCH)
-            .expect("Time went backwards");
-        self.version = since_the_epoch.as_millis();
-        self.drawn = false;
+        progress.finish();
+        maze
+    }
+
+    fn build<C: AbstractCell, T: AbstractGrid<C> + GtkDrawable + 'static>(
+        &self,
+        mut g: Box<T>,
+        rows: usize,
+        cols: usize,
+        progress: &Progress,
+    ) -> Option<Maze> {
+        make_tha_maze_with_progress(&self.generation_type, &mut *g, self.braid_chance, progress);
+        if progress.is_cancelled() {
+            return None;
+        }
+        Some(self.finish(g, rows, cols))
+    }
+
+    /// Solves a finished grid and works out its code.
+    fn finish<C: AbstractCell, T: AbstractGrid<C> + GtkDrawable + 'static>(
+        &self,
+        g: Box<T>,
+        rows: usize,
+        cols: usize,
+    ) -> Maze {
+        let wrap = match self.maze_type {
+            MazeType::Regular => self.wrap.clone(),
+            _ => rectangle::Wrap::None,
+        };
+        let step = solve::solve_with_longest_path(&*g);
+        // Tunnels depend on the order links are made in, which maze codes do not keep, and
+        // the code header has no room for the number of levels or polar ring options.
+        let custom_rings = self.polar != polar::PolarOptions::default();
+        let code = match self.maze_type {
+            MazeType::Weave | MazeType::ThreeD => String::new(),
+            MazeType::Circular if custom_rings => String::new(),
+            _ => codec::encode(&*g, &self.maze_type, rows, cols, self.mask.as_ref(), &wrap)
+                .unwrap_or_default(),
+        };
+        let puzzle = (self.doors > 0)
+            .then(|| Puzzle::place(&*g, &step, self.doors, &mut rand::rng()));
+        Maze {
+            stats: stats::analyze(&*g),
+            grid: g,
+            step,
+            code,
+            puzzle,
+        }
     }
 }
 
-pub fn draw_grid_mutex(
-    img: &gtk::DrawingArea,
-    g: Arc<RwLock<Settings>>,
-    generation_type: GenerationType,
-    maze_type: MazeType,
-    braid_chance: u8,
-    version: u128,
-) {
-    let cellsize = 10.;
+/// Milliseconds between renders while the maze area is resized, zoomed or panned.
+const VIEW_REDRAW_MS: u64 = 150;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Rerender {
+    Idle,
+    Waiting,
+    Due,
+}
+
+/// Rendered maze with the version and placement it was rendered at.
+struct DrawCache {
+    surface: gtk::cairo::ImageSurface,
+    version: u64,
+    width: i32,
+    height: i32,
+    placement: Placement,
+}
+
+/// Connects the one draw handler of the maze area, which shows whatever `settings` holds.
+/// The maze is rendered to a surface that is painted again as long as the version, size
+/// and placement stay the same. While the area is resized, zoomed or panned the old surface
+/// is moved into place and the maze rendered again at most every `VIEW_REDRAW_MS`.
+pub fn connect_maze_drawing(img: &gtk::DrawingArea, settings: Arc<RwLock<Settings>>) {
+    let cached: RefCell<Option<DrawCache>> = RefCell::new(None);
+    let rerender = Rc::new(Cell::new(Rerender::Idle));
     img.connect_draw(move |w, cr| {
-        {
-            let mut data = g.write().unwrap();
-            if data.maze_type == maze_type
-                && data.generation_type == generation_type
-                && data.braid_chance == braid_chance
-                && data.version == version
-            // TODO: This is intended to fix re-drawing without need. For some reason does not work
-            // really
-            // && !data.drawn
-            {
-                let settings = &*data;
-                // println!("- draw pathfind - ");
-                settings.grid.draw_pathfind(w, cr, &settings.step, cellsize);
-                // println!("- draw maze - ");
-                settings.grid.draw_maze(w, cr, cellsize);
-                // println!("- draw: moving on - ");
-                data.drawn = true
-            } else {
-                println!(
-                    "No redraw yay: {:?}, {:?}, {:}, {:}, {:}",
-                    data.maze_type,
-                    data.generation_type,
-                    data.braid_chance,
-                    data.version,
-                    data.drawn
-                )
+        let settings = settings.read().unwrap();
+        let (width, height) = (w.allocated_width(), w.allocated_height());
+        let placement = settings.placement(width as f64, height as f64);
+        let mut cache = cached.borrow_mut();
+        let render_now = match &*cache {
+            Some(c) if c.version == settings.version => {
+                let moved = c.width != width || c.height != height || c.placement != placement;
+                if moved && rerender.get() == Rerender::Idle {
+                    rerender.set(Rerender::Waiting);
+                    let (w, rerender) = (w.clone(), rerender.clone());
+                    gtk::glib::timeout_add_local_once(
+                        Duration::from_millis(VIEW_REDRAW_MS),
+                        move || {
+                            rerender.set(Rerender::Due);
+                            w.queue_draw();
+                        },
+                    );
+                }
+                moved && rerender.get() == Rerender::Due
             }
+            _ => true,
+        };
+        if render_now {
+            rerender.set(Rerender::Idle);
+            let surface =
+                gtk::cairo::ImageSurface::create(gtk::cairo::Format::ARgb32, width, height)
+                    .expect("error while creating surface");
+            let surface_cr =
+                gtk::cairo::Context::new(&surface).expect("error while creating context");
+            settings.render(w, &surface_cr);
+            *cache = Some(DrawCache {
+                surface,
+                version: settings.version,
+                width,
+                height,
+                placement,
+            });
         }
+
+        // the same drawing at the old placement, moved to the new one
+        let c = cache.as_ref().expect("maze was rendered");
+        let k = placement.scale / c.placement.scale;
+        cr.save().expect("error while saving coords");
+        cr.translate(
+            placement.x - k * c.placement.x,
+            placement.y - k * c.placement.y,
+        );
+        cr.scale(k, k);
+        cr.set_source_surface(&c.surface, 0., 0.)
+            .expect("error while drawing surface");
+        cr.paint().expect("error while drawing surface");
+        cr.restore().expect("error while restoring coords");
+        settings.draw_game(w, cr);
         Propagation::Proceed
     });
 }
@@ -0,0 +1,85 @@
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition};
+
+// Graphviz positions are in points; one grid unit becomes this many points.
+const POINTS_PER_UNIT: f64 = 36.;
+
+fn node_label<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(g: &T, ix: usize) -> String {
+    g.cell(ix).label()
+}
+
+/// Every link exactly once, as (lower index, higher index).
+fn edges<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(g: &T) -> Vec<(usize, usize)> {
+    let mut res = Vec::new();
+    for i in 0..g.len() {
+        let mut links: Vec<usize> = g.links(i).into_iter().filter(|&ix| ix > i).collect();
+        links.sort();
+        res.extend(links.into_iter().map(|ix| (i, ix)));
+    }
+    res
+}
+
+/// Undirected DOT graph of the maze passages. With `with_positions`, nodes get pinned
+/// `pos` attributes so `neato -n` reproduces the on-screen layout.
+pub fn to_dot<C: AbstractCell + ?Sized, T: AbstractGrid<C> + CellPosition + ?Sized>(
+    g: &T,
+    with_positions: bool,
+) -> String {
+    let mut res = "graph g {\n".to_owned();
+    for i in (0..g.len()).filter(|&i| g.is_active(i)) {
+        res.push_str(format!("  n{} [label=\"{}\"", i, node_label(g, i)).as_str());
+        if with_positions {
+            let (x, y) = g.position(i);
+            res.push_str(
+                format!(
+                    ", pos=\"{:.2},{:.2}!\"",
+                    x * POINTS_PER_UNIT,
+                    -y * POINTS_PER_UNIT
+                )
+                .as_str(),
+            );
+        }
+        res.push_str("]\n");
+    }
+    for (a, b) in edges(g) {
+        res.push_str(format!("  n{} -- n{}\n", a, b).as_str());
+    }
+    res.push_str("}\n");
+    res
+}
+
+/// GraphML document of the maze passages, with label and row/col (and optionally x/y) node
+/// data.
+pub fn to_graphml<C: AbstractCell + ?Sized, T: AbstractGrid<C> + CellPosition + ?Sized>(
+    g: &T,
+    with_positions: bool,
+) -> String {
+    let mut res = String::new();
+    res.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    res.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    res.push_str("  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n");
+    res.push_str("  <key id=\"row\" for=\"node\" attr.name=\"row\" attr.type=\"int\"/>\n");
+    res.push_str("  <key id=\"col\" for=\"node\" attr.name=\"col\" attr.type=\"int\"/>\n");
+    if with_positions {
+        res.push_str("  <key id=\"x\" for=\"node\" attr.name=\"x\" attr.type=\"double\"/>\n");
+        res.push_str("  <key id=\"y\" for=\"node\" attr.name=\"y\" attr.type=\"double\"/>\n");
+    }
+    res.push_str("  <graph id=\"maze\" edgedefault=\"undirected\">\n");
+    for i in (0..g.len()).filter(|&i| g.is_active(i)) {
+        let c = g.cell(i);
+        res.push_str(format!("    <node id=\"n{}\">\n", i).as_str());
+        res.push_str(format!("      <data key=\"label\">{}</data>\n", node_label(g, i)).as_str());
+        res.push_str(format!("      <data key=\"row\">{}</data>\n", c.row()).as_str());
+        res.push_str(format!("      <data key=\"col\">{}</data>\n", c.col()).as_str());
+        if with_positions {
+            let (x, y) = g.position(i);
+            res.push_str(format!("      <data key=\"x\">{:.4}</data>\n", x).as_str());
+            res.push_str(format!("      <data key=\"y\">{:.4}</data>\n", y).as_str());
+        }
+        res.push_str("    </node>\n");
+    }
+    for (a, b) in edges(g) {
+        res.push_str(format!("    <edge source=\"n{}\" target=\"n{}\"/>\n", a, b).as_str());
+    }
+    res.push_str("  </graph>\n</graphml>\n");
+    res
+}
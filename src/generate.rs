@@ -1,6 +1,53 @@
-use crate::grid::{AbstractCell, AbstractGrid, CompassDirections, CompassGrid, RectangularGrid};
+use crate::grid::{AbstractCell, AbstractGrid, CompassGrid};
 use rand::prelude::*;
 use std::collections::{BinaryHeap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// How far a generator run has got, shared with other threads: cells carved out of the
+/// cells to carve, and a flag asking the generator to stop early.
+#[derive(Default)]
+pub struct Progress {
+    carved: AtomicUsize,
+    total: AtomicUsize,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+}
+
+impl Progress {
+    pub fn start(&self, total: usize) {
+        self.carved.store(0, Ordering::Relaxed);
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    fn carve(&self) {
+        self.carved.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn carved(&self) -> usize {
+        self.carved.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> usize {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Marks the run as over, whether it completed or was cancelled.
+    pub fn finish(&self) {
+        self.finished.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Relaxed)
+    }
+}
 
 fn random_neighbor<T: Copy>(neighbors: &[Option<T>], r: &mut rand::rngs::ThreadRng) -> Option<T> {
     let results: Vec<T> = neighbors.iter().filter_map(|x| *x).collect();
@@ -11,44 +58,136 @@ fn random_neighbor<T: Copy>(neighbors: &[Option<T>], r: &mut rand::rngs::ThreadR
     Some(results[r.random_range(0..results.len())])
 }
 
-#[allow(dead_code)]
+/// Whether the cell still needs to be carved into: active, with neighbours, but no links.
+fn is_unfilled<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(g: &T, ix: usize) -> bool {
+    g.links(ix).is_empty() && !g.neighbours(ix).is_empty()
+}
+
+/// Any cell left untouched by a generator run, e.g. in a part of a masked grid that is not
+/// connected to where the generator started.
+pub fn first_unfilled<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+) -> Option<usize> {
+    (0..g.len()).find(|&ix| is_unfilled(g, ix))
+}
+
+fn random_start<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+    r: &mut rand::rngs::ThreadRng,
+) -> Option<usize> {
+    let candidates: Vec<usize> = (0..g.len()).filter(|&ix| is_unfilled(g, ix)).collect();
+    if candidates.is_empty() {
+        return None;
+    }
+    Some(candidates[r.random_range(0..candidates.len())])
+}
+
+/// Uses `preferred` as the starting cell when it can be carved, a random cell otherwise.
+fn start_at<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+    r: &mut rand::rngs::ThreadRng,
+    preferred: usize,
+) -> Option<usize> {
+    if is_unfilled(g, preferred) {
+        Some(preferred)
+    } else {
+        random_start(g, r)
+    }
+}
+
+fn component_size<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+    start: usize,
+) -> usize {
+    let mut seen = HashSet::new();
+    let mut queue = VecDeque::new();
+    seen.insert(start);
+    queue.push_back(start);
+    while let Some(ix) = queue.pop_front() {
+        for n in g.neighbours(ix) {
+            if seen.insert(n) {
+                queue.push_back(n);
+            }
+        }
+    }
+    seen.len()
+}
+
+/// Active neighbour to the north, without crossing the seam of a wrapped grid.
+fn north_of<C: AbstractCell + ?Sized, T: CompassGrid<C> + ?Sized>(
+    g: &T,
+    ix: usize,
+) -> Option<usize> {
+    g.north_ix(ix)
+        .filter(|&n| g.is_active(n) && g.cell(n).row() < g.cell(ix).row())
+}
+
+/// Active neighbour to the east, without crossing the seam of a wrapped grid.
+fn east_of<C: AbstractCell + ?Sized, T: CompassGrid<C> + ?Sized>(
+    g: &T,
+    ix: usize,
+) -> Option<usize> {
+    g.east_ix(ix)
+        .filter(|&n| g.is_active(n) && g.cell(n).col() > g.cell(ix).col())
+}
+
+/// Links every cell to its north or east neighbour, so the north and east borders become
+/// straight corridors and passages lean north east. Masked grids can be left in several
+/// parts.
 pub fn binary_tree<C: AbstractCell + ?Sized, T: CompassGrid<C> + ?Sized>(
     g: &mut T,
     r: &mut rand::rngs::ThreadRng,
+    progress: &Progress,
 ) {
     for i in 0..g.len() {
-        if let Some(neighbor) = random_neighbor(&[g.north_ix(i), g.east_ix(i)], r) {
-            g.link(i, neighbor)
+        if progress.is_cancelled() {
+            return;
+        }
+        if !g.is_active(i) {
+            continue;
+        }
+        if let Some(neighbor) = random_neighbor(&[north_of(g, i), east_of(g, i)], r) {
+            g.link(i, neighbor);
+            progress.carve();
         }
     }
 }
 
-#[allow(dead_code)]
-pub fn sidewinder<
-    C: AbstractCell + ?Sized,
-    T: RectangularGrid + CompassDirections + AbstractGrid<C> + ?Sized,
->(
+/// Carves runs of cells linked east along each row, closing every run with a passage north
+/// out of one of its cells. Takes the cells in index order, which has to be row by row
+/// from the north as in rect and weave grids. The north border becomes one corridor;
+/// masked grids can be left in several parts.
+pub fn sidewinder<C: AbstractCell + ?Sized, T: CompassGrid<C> + ?Sized>(
     g: &mut T,
     r: &mut rand::rngs::ThreadRng,
+    progress: &Progress,
 ) {
-    for i in 0..g.height() {
-        let mut current_run = Vec::new();
-        for j in 0..g.width() {
-            current_run.push((i, j));
-
-            let should_close_out = (j == g.width() - 1) || (i > 1 && r.random_bool(0.5));
-
-            if should_close_out {
-                let (r_i, r_j) = current_run[r.random_range(0..current_run.len())];
-                let ix2 = g.ix(r_i, r_j);
-                current_run.clear();
-                if let Some(ix1) = g.north_ix(ix2) {
-                    g.link(ix1, ix2)
+    // cells of the current run with a way north
+    let mut run = Vec::new();
+    for i in 0..g.len() {
+        if progress.is_cancelled() {
+            return;
+        }
+        if !g.is_active(i) {
+            continue;
+        }
+        if north_of(g, i).is_some() {
+            run.push(i);
+        }
+        match east_of(g, i) {
+            Some(east) if run.is_empty() || r.random_bool(0.5) => {
+                g.link(i, east);
+                progress.carve();
+            }
+            _ => {
+                if !run.is_empty() {
+                    let ix = run[r.random_range(0..run.len())];
+                    if let Some(north) = north_of(g, ix) {
+                        g.link(ix, north);
+                        progress.carve();
+                    }
                 }
-            } else {
-                let ix1 = g.ix(i, j);
-                let ix2 = g.east_ix(ix1).unwrap();
-                g.link(ix1, ix2);
+                run.clear();
             }
         }
     }
@@ -58,19 +197,24 @@ pub fn sidewinder<
 pub fn aldous_broder<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
     g: &mut T,
     r: &mut rand::rngs::ThreadRng,
+    progress: &Progress,
 ) {
+    let mut current_cell = match random_start(g, r) {
+        Some(ix) => ix,
+        None => return,
+    };
     let mut visited = HashSet::new();
-    let target_size = g.len();
-    let mut current_cell = r.random_range(0..g.len());
+    let target_size = component_size(g, current_cell);
     visited.insert(current_cell);
 
-    while visited.len() < target_size {
+    while visited.len() < target_size && !progress.is_cancelled() {
         // At least one neighbor is guaranteed to exist, unwrap is safe
         let neighbours = g.neighbours(current_cell);
         let random_neighbor = neighbours[r.random_range(0..neighbours.len())];
 
         if !visited.contains(&random_neighbor) {
             g.link(random_neighbor, current_cell);
+            progress.carve();
         }
         visited.insert(random_neighbor);
         current_cell = random_neighbor;
@@ -130,12 +274,16 @@ pub fn hunt_and_kill<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
 pub fn recursive_backtracker<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
     g: &mut T,
     r: &mut rand::rngs::ThreadRng,
+    progress: &Progress,
 ) {
-    let current_idx = r.random_range(0..g.len());
+    let current_idx = match random_start(g, r) {
+        Some(ix) => ix,
+        None => return,
+    };
     let mut cell_stack = VecDeque::new();
     cell_stack.push_back(current_idx);
 
-    while !cell_stack.is_empty() {
+    while !cell_stack.is_empty() && !progress.is_cancelled() {
         let current_idx = *cell_stack.back().unwrap();
         let neighbors = unvisited_neighbors(g, current_idx);
         if neighbors.is_empty() {
@@ -143,6 +291,7 @@ pub fn recursive_backtracker<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Siz
         } else {
             let n_ix = neighbors[r.random_range(0..neighbors.len())];
             g.link(current_idx, n_ix);
+            progress.carve();
             cell_stack.push_back(n_ix);
         }
     }
@@ -152,11 +301,14 @@ pub fn recursive_backtracker<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Siz
 pub fn simplified_prim<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
     g: &mut T,
     r: &mut rand::rngs::ThreadRng,
+    progress: &Progress,
 ) {
     let mut active = Vec::new();
-    let start_at = g.len() / 2;
-    active.push(start_at);
-    while !active.is_empty() {
+    match start_at(g, r, g.len() / 2) {
+        Some(ix) => active.push(ix),
+        None => return,
+    }
+    while !active.is_empty() && !progress.is_cancelled() {
         let current_cell = active[r.random_range(0..active.len())];
         let neighbors = unvisited_neighbors(g, current_cell);
         if neighbors.is_empty() {
@@ -164,6 +316,7 @@ pub fn simplified_prim<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
         } else {
             let n_ix = neighbors[r.random_range(0..neighbors.len())];
             g.link(current_cell, n_ix);
+            progress.carve();
             active.push(n_ix);
         }
     }
@@ -173,12 +326,14 @@ pub fn simplified_prim<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
 pub fn true_prim<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
     g: &mut T,
     r: &mut rand::rngs::ThreadRng,
+    progress: &Progress,
 ) {
-    println!(" - prim - ");
     let mut active = BinaryHeap::new();
-    let start_at: usize = 0;
-    active.push((r.next_u64(), start_at));
-    while !active.is_empty() {
+    match start_at(g, r, 0) {
+        Some(ix) => active.push((r.next_u64(), ix)),
+        None => return,
+    }
+    while !active.is_empty() && !progress.is_cancelled() {
         let (_, current_cell) = active.peek().unwrap();
         let neighbors = unvisited_neighbors(g, *current_cell);
         if neighbors.is_empty() {
@@ -186,10 +341,78 @@ pub fn true_prim<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
         } else {
             let n_ix = neighbors[r.random_range(0..neighbors.len())];
             g.link(*current_cell, n_ix);
+            progress.carve();
             active.push((r.next_u64(), n_ix));
         }
     }
-    println!(" - end prim - ");
+}
+
+/// Union-find over cell indices.
+pub struct DisjointSets {
+    parent: Vec<usize>,
+}
+
+impl DisjointSets {
+    pub fn new(len: usize) -> DisjointSets {
+        DisjointSets {
+            parent: (0..len).collect(),
+        }
+    }
+
+    pub fn find(&mut self, ix: usize) -> usize {
+        let mut root = ix;
+        while self.parent[root] != root {
+            root = self.parent[root];
+        }
+        let mut cur = ix;
+        while self.parent[cur] != root {
+            let next = self.parent[cur];
+            self.parent[cur] = root;
+            cur = next;
+        }
+        root
+    }
+
+    /// Merges the sets of `a` and `b`, false if they already were the same set.
+    pub fn union(&mut self, a: usize, b: usize) -> bool {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return false;
+        }
+        self.parent[a] = b;
+        true
+    }
+}
+
+/// Links already in the grid are kept and count as merged sets, so passages can be
+/// placed before running it (e.g. weave crossings).
+#[allow(dead_code)]
+pub fn kruskal<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &mut T,
+    r: &mut rand::rngs::ThreadRng,
+    progress: &Progress,
+) {
+    let mut sets = DisjointSets::new(g.len());
+    let mut edges = Vec::new();
+    for i in 0..g.len() {
+        for n in g.links(i) {
+            sets.union(i, n);
+        }
+        for n in g.neighbours(i).into_iter().filter(|&n| n > i) {
+            edges.push((i, n));
+        }
+    }
+    edges.shuffle(r);
+    for (a, b) in edges {
+        if progress.is_cancelled() {
+            return;
+        }
+        // linking can change what is a neighbour, e.g. in weave grids
+        if g.neighbours(a).contains(&b) && sets.union(a, b) {
+            g.link(a, b);
+            progress.carve();
+        }
+    }
 }
 
 #[allow(dead_code)]
@@ -218,3 +441,48 @@ pub fn braid<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::draw_utils::{make_tha_maze, GenerationType};
+    use crate::grid::AbstractGrid;
+    use crate::mask::Mask;
+    use crate::rectangle::{RegularGrid, Wrap};
+    use crate::{solve, stats};
+
+    /// Checks that the maze is perfect: no loops and every active cell reachable.
+    fn assert_perfect(g: &RegularGrid) {
+        assert_eq!(stats::analyze(g).loops, 0);
+        let step = solve::solve_with_longest_path(g);
+        for ix in (0..g.len()).filter(|&ix| g.is_active(ix)) {
+            assert!(
+                step.cell_weights[ix].path_length >= 0,
+                "cell {} cut off",
+                ix
+            );
+        }
+    }
+
+    #[test]
+    fn compass_generators_make_perfect_mazes() {
+        // a wall down the middle with a gap at the bottom, which neither generator carves
+        // through on its own
+        let mut mask = Mask::new(8, 8);
+        for row in 0..7 {
+            mask.set(row, 3, false);
+        }
+        for generation_type in [GenerationType::BinaryTree, GenerationType::Sidewinder] {
+            for _ in 0..10 {
+                let mut g = RegularGrid::masked(mask.clone());
+                make_tha_maze(&generation_type, &mut g, 0);
+                assert_perfect(&g);
+                for wrap in [Wrap::Cylinder, Wrap::Torus, Wrap::Moebius] {
+                    let mut g = RegularGrid::new(6, 7);
+                    g.wrap = wrap;
+                    make_tha_maze(&generation_type, &mut g, 0);
+                    assert_perfect(&g);
+                }
+            }
+        }
+    }
+}
@@ -12,6 +12,16 @@ pub trait AbstractGrid<T: AbstractCell + ?Sized> {
     fn links(&self, ix: usize) -> HashSet<usize> {
         self.cell(ix).links().iter().cloned().collect()
     }
+    /// Masked out cells stay in the grid but have no neighbours and are skipped by
+    /// generators, solvers and renderers.
+    fn is_active(&self, _ix: usize) -> bool {
+        true
+    }
+    /// The grid with its compass directions, for the generators that carve north and east
+    /// only; None for grids without them.
+    fn as_compass(&mut self) -> Option<&mut dyn CompassGrid<T>> {
+        None
+    }
 }
 
 pub trait CompassDirections {
@@ -39,9 +49,19 @@ pub trait RectangularGrid {
     }
 }
 
+/// Cell centres in grid units, used by exporters that want to preserve the drawn layout.
+/// The y axis points down, as in the GTK drawings.
+pub trait CellPosition {
+    fn position(&self, ix: usize) -> (f64, f64);
+}
+
 pub trait AbstractCell {
     fn row(&self) -> usize;
     fn col(&self) -> usize;
     fn links(&self) -> HashSet<usize>;
     fn link(&mut self, ix: usize);
+    /// Name of the cell in exported graphs, unique within its grid.
+    fn label(&self) -> String {
+        format!("({},{})", self.row(), self.col())
+    }
 }
@@ -0,0 +1,304 @@
+use crate::draw_utils::GtkDrawable;
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition, CompassDirections, RectangularGrid};
+use crate::gtk::prelude::WidgetExt;
+use crate::play::{self, Move};
+use crate::solve::DijkstraStep;
+use gtk::cairo::Context;
+use gtk::DrawingArea;
+use std::collections::HashSet;
+use std::f64::consts::PI;
+
+#[derive(Clone)]
+pub struct Cell3D {
+    pub level: usize,
+    pub row: usize,
+    pub col: usize,
+    pub links: HashSet<usize>,
+}
+
+impl AbstractCell for Cell3D {
+    fn row(&self) -> usize {
+        self.row
+    }
+
+    fn col(&self) -> usize {
+        self.col
+    }
+
+    fn links(&self) -> HashSet<usize> {
+        self.links.iter().cloned().collect()
+    }
+
+    fn link(&mut self, ix: usize) {
+        self.links.insert(ix);
+    }
+
+    fn label(&self) -> String {
+        format!("({},{},{})", self.level, self.row, self.col)
+    }
+}
+
+/// Stacked rectangular floors, connected by stairs between cells at the same row and
+/// column of neighbouring levels.
+#[derive(Clone)]
+pub struct Grid3D {
+    pub levels: usize,
+    pub height: usize,
+    pub width: usize,
+    pub cells: Vec<Cell3D>,
+}
+
+impl RectangularGrid for Grid3D {
+    fn width(&self) -> usize {
+        self.width
+    }
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl CompassDirections for Grid3D {
+    fn north_ix(&self, ix: usize) -> Option<usize> {
+        let c = &self.cells[ix];
+        self.ix3_opt(c.level, c.row.wrapping_sub(1), c.col)
+    }
+
+    fn east_ix(&self, ix: usize) -> Option<usize> {
+        let c = &self.cells[ix];
+        self.ix3_opt(c.level, c.row, c.col.wrapping_add(1))
+    }
+
+    fn south_ix(&self, ix: usize) -> Option<usize> {
+        let c = &self.cells[ix];
+        self.ix3_opt(c.level, c.row.wrapping_add(1), c.col)
+    }
+
+    fn west_ix(&self, ix: usize) -> Option<usize> {
+        let c = &self.cells[ix];
+        self.ix3_opt(c.level, c.row, c.col.wrapping_sub(1))
+    }
+}
+
+impl Grid3D {
+    pub fn new(levels: usize, rows: usize, cols: usize) -> Grid3D {
+        let mut cells = Vec::new();
+        for level in 0..levels {
+            for row in 0..rows {
+                for col in 0..cols {
+                    cells.push(Cell3D {
+                        level,
+                        row,
+                        col,
+                        links: HashSet::new(),
+                    });
+                }
+            }
+        }
+        Grid3D {
+            levels,
+            height: rows,
+            width: cols,
+            cells,
+        }
+    }
+
+    pub fn ix3_opt(&self, level: usize, row: usize, col: usize) -> Option<usize> {
+        if level >= self.levels {
+            return None;
+        }
+        self.ix_opt(row, col)
+            .map(|ix| ix + level * self.width * self.height)
+    }
+
+    pub fn up_ix(&self, ix: usize) -> Option<usize> {
+        let c = &self.cells[ix];
+        self.ix3_opt(c.level + 1, c.row, c.col)
+    }
+
+    pub fn down_ix(&self, ix: usize) -> Option<usize> {
+        let c = &self.cells[ix];
+        self.ix3_opt(c.level.wrapping_sub(1), c.row, c.col)
+    }
+
+    /// Top left corner of a cell in the drawing, where levels sit left to right with one
+    /// cell of space between them.
+    fn corner(&self, ix: usize, cellsize: f64) -> (f64, f64) {
+        let c = &self.cells[ix];
+        let x = (c.level * (self.width + 1) + c.col) as f64 * cellsize;
+        (x, c.row as f64 * cellsize)
+    }
+}
+
+impl CellPosition for Grid3D {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        let (x, y) = self.corner(ix, 1.);
+        (x + 0.5, y + 0.5)
+    }
+}
+
+impl AbstractGrid<Cell3D> for Grid3D {
+    fn neighbours(&self, ix: usize) -> Vec<usize> {
+        [
+            self.north_ix(ix),
+            self.east_ix(ix),
+            self.south_ix(ix),
+            self.west_ix(ix),
+            self.up_ix(ix),
+            self.down_ix(ix),
+        ]
+        .iter()
+        .filter_map(|x| *x)
+        .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn cell(&self, ix: usize) -> &Cell3D {
+        &self.cells[ix]
+    }
+
+    fn cell_mut(&mut self, ix: usize) -> &mut Cell3D {
+        &mut self.cells[ix]
+    }
+}
+
+impl GtkDrawable for Grid3D {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        let cols = self.levels * (self.width + 1) - 1;
+        (cols as f64 * cellsize, self.height as f64 * cellsize)
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        let target = match towards {
+            Move::Up => self.up_ix(ix),
+            Move::Down => self.down_ix(ix),
+            _ => return play::compass_move(self, ix, towards),
+        };
+        target.filter(|n| self.cells[ix].links.contains(n))
+    }
+
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        let (x, y) = self.corner(ix, cellsize);
+        cr.rectangle(x, y, cellsize, cellsize);
+    }
+
+    fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+        cr.set_line_width(1.0);
+
+        for ix in 0..self.len() {
+            let links = &self.cells[ix].links;
+            let wall = |n: Option<usize>| n.is_none_or(|n| !links.contains(&n));
+            let (x1, y1) = self.corner(ix, cellsize);
+            let (x2, y2) = (x1 + cellsize, y1 + cellsize);
+            let side = |closed: bool, x: f64, y: f64| {
+                if closed {
+                    cr.line_to(x, y);
+                } else {
+                    cr.move_to(x, y);
+                }
+            };
+            cr.move_to(x1, y1);
+            side(wall(self.north_ix(ix)), x2, y1);
+            side(wall(self.east_ix(ix)), x2, y2);
+            side(wall(self.south_ix(ix)), x1, y2);
+            side(wall(self.west_ix(ix)), x1, y1);
+            cr.stroke().expect("error while drawing stroke");
+
+            // stairs: a triangle pointing up in the top half, down in the bottom half
+            let (cx, q) = (x1 + cellsize / 2., cellsize / 4.);
+            if !wall(self.up_ix(ix)) {
+                cr.move_to(cx - q, y1 + 2. * q);
+                cr.line_to(cx, y1 + q / 2.);
+                cr.line_to(cx + q, y1 + 2. * q);
+                cr.close_path();
+                cr.fill().expect("error while drawing stroke");
+            }
+            if !wall(self.down_ix(ix)) {
+                cr.move_to(cx - q, y2 - 2. * q);
+                cr.line_to(cx, y2 - q / 2.);
+                cr.line_to(cx + q, y2 - 2. * q);
+                cr.close_path();
+                cr.fill().expect("error while drawing stroke");
+            }
+        }
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn draw_pathfind(
+        &self,
+        w: &DrawingArea,
+        cr: &Context,
+        step_state: &DijkstraStep,
+        cellsize: f64,
+    ) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let path = step_state.path_to(max_idx);
+
+        for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 {
+                continue;
+            }
+            let intensity = (max_length - c.path_length) as f64 / max_length as f64;
+            cr.set_source_rgb(intensity, 0.5 + intensity / 2., intensity);
+            let (x, y) = self.corner(i, cellsize);
+            cr.rectangle(x, y, cellsize, cellsize);
+            cr.fill().expect("error while drawing stroke");
+        }
+
+        let centre = |ix: usize| {
+            let (x, y) = self.corner(ix, cellsize);
+            (x + cellsize / 2., y + cellsize / 2.)
+        };
+        cr.set_line_width(1.0);
+        cr.set_source_rgb(0., 0., 0.);
+        for ix in [path.first(), path.last()].into_iter().flatten() {
+            let (x, y) = centre(*ix);
+            cr.new_sub_path();
+            cr.arc(x, y, cellsize / 2., 0., 2. * PI);
+            cr.stroke().expect("error while drawing stroke");
+        }
+
+        // taking the stairs jumps to the next level's drawing, so only walk within levels
+        cr.set_source_rgb(1., 0., 0.);
+        cr.set_line_width(4.0);
+        for pair in path.windows(2) {
+            let (x1, y1) = centre(pair[0]);
+            let (x2, y2) = centre(pair[1]);
+            if self.cells[pair[0]].level == self.cells[pair[1]].level {
+                cr.move_to(x1, y1);
+                cr.line_to(x2, y2);
+            }
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let x = x * canvas_width / w.allocated_width() as f64;
+        let y = y * canvas_height / w.allocated_height() as f64;
+        if x < 0. || y < 0. {
+            return None;
+        }
+        let col = (x / cellsize).floor() as usize % (self.width + 1);
+        let row = (y / cellsize).floor() as usize;
+        self.ix_opt(row, col)?;
+        Some((row, col))
+    }
+}
@@ -1,8 +1,10 @@
 use std::collections::HashSet;
 
-use crate::draw_utils::GtkDrawable;
-use crate::grid::{AbstractCell, AbstractGrid, RectangularGrid};
+use crate::draw_utils::{self, GtkDrawable};
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition, RectangularGrid};
 use crate::gtk::prelude::WidgetExt;
+use crate::mask::Mask;
+use crate::play::Move;
 use crate::rectangle::Cell;
 use crate::solve::DijkstraStep;
 use gtk::cairo::Context;
@@ -13,10 +15,14 @@ pub struct HexagonalGrid {
     pub height: usize,
     pub width: usize,
     pub cells: Vec<Cell>,
+    pub mask: Mask,
 }
 
 impl AbstractGrid<Cell> for HexagonalGrid {
     fn neighbours(&self, ix: usize) -> Vec<usize> {
+        if !self.is_active(ix) {
+            return Vec::new();
+        }
         let neighbors = [
             self.north_ix(ix),
             self.south_ix(ix),
@@ -26,10 +32,18 @@ impl AbstractGrid<Cell> for HexagonalGrid {
             self.southwest_ix(ix),
         ];
 
-        let neighbors: Vec<usize> = neighbors.iter().filter_map(|x| *x).collect();
+        let neighbors: Vec<usize> = neighbors
+            .iter()
+            .filter_map(|x| *x)
+            .filter(|&x| self.is_active(x))
+            .collect();
         neighbors
     }
 
+    fn is_active(&self, ix: usize) -> bool {
+        self.mask.is_on(self.cells[ix].row, self.cells[ix].col)
+    }
+
     fn len(&self) -> usize {
         self.cells.len()
     }
@@ -44,17 +58,23 @@ impl AbstractGrid<Cell> for HexagonalGrid {
 }
 
 impl HexagonalGrid {
+    #[allow(dead_code)]
     pub fn new(rows: usize, cols: usize) -> HexagonalGrid {
+        HexagonalGrid::masked(Mask::new(rows, cols))
+    }
+
+    pub fn masked(mask: Mask) -> HexagonalGrid {
         let mut gridarr = Vec::new();
-        for i in 0..rows {
-            for j in 0..cols {
+        for i in 0..mask.rows {
+            for j in 0..mask.cols {
                 gridarr.push(Cell::new(i, j));
             }
         }
         HexagonalGrid {
-            width: cols,
-            height: rows,
+            width: mask.cols,
+            height: mask.rows,
             cells: gridarr,
+            mask,
         }
     }
 
@@ -108,6 +128,43 @@ impl HexagonalGrid {
         let col = self.cells[ix].col;
         self.ix_opt(row.wrapping_sub(1), col)
     }
+
+    /// Walls of cell `ix` clockwise from north; true where there is no passage.
+    pub fn walls(&self, ix: usize) -> [bool; 6] {
+        let links = &self.cells[ix].links;
+        [
+            self.north_ix(ix),
+            self.northeast_ix(ix),
+            self.southeast_ix(ix),
+            self.south_ix(ix),
+            self.southwest_ix(ix),
+            self.northwest_ix(ix),
+        ]
+        .map(|n| n.map(|n| !links.contains(&n)).unwrap_or(true))
+    }
+}
+
+/// Mask for a hexagon made of hexes, `across` cells from corner to corner through the
+/// middle one; even sizes get the next odd one, as a hexagon needs a middle cell.
+pub fn hexagon_mask(across: usize) -> Mask {
+    let radius = across / 2;
+    let size = 2 * radius + 1;
+    let mut mask = Mask::new(size, size);
+    // cube coordinates, with odd columns shifted down as in `north_diag`
+    let cube = |row: usize, col: usize| {
+        let (row, col) = (row as i64, col as i64);
+        let z = row - (col - (col & 1)) / 2;
+        [col, z, -col - z]
+    };
+    let centre = cube(radius, radius);
+    for row in 0..size {
+        for col in 0..size {
+            let c = cube(row, col);
+            let dist = (0..3).map(|k| (c[k] - centre[k]).abs()).max().unwrap();
+            mask.set(row, col, dist <= radius as i64);
+        }
+    }
+    mask
 }
 
 impl RectangularGrid for HexagonalGrid {
@@ -129,6 +186,12 @@ fn center_coords(row: usize, col: usize, cellsize: f64) -> (f64, f64) {
     (cx, cy)
 }
 
+impl CellPosition for HexagonalGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        center_coords(self.cells[ix].row, self.cells[ix].col, 1.)
+    }
+}
+
 struct HexagonalCoords {
     pub x_fw: f64,
     pub x_nw: f64,
@@ -171,19 +234,56 @@ fn hex_points(row: usize, col: usize, cellsize: f64) -> HexagonalCoords {
 }
 
 impl GtkDrawable for HexagonalGrid {
-    fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
-        cr.save().expect("error while saving coords");
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
         let a = cellsize / 2.;
         let b = cellsize * 3f64.sqrt() / 2.;
+        (
+            3. * self.width as f64 * a + a,
+            2. * self.height as f64 * b + b + 0.1 * cellsize,
+        )
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        let target = match towards {
+            Move::North => self.north_ix(ix),
+            Move::South => self.south_ix(ix),
+            Move::NorthEast => self.northeast_ix(ix),
+            Move::NorthWest => self.northwest_ix(ix),
+            Move::SouthEast => self.southeast_ix(ix),
+            Move::SouthWest => self.southwest_ix(ix),
+            _ => None,
+        };
+        target.filter(|n| self.cells[ix].links.contains(n))
+    }
+
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        let c = &self.cells[ix];
+        let p = hex_points(c.row, c.col, cellsize);
+        draw_utils::polygon_path(
+            cr,
+            &[
+                (p.x_fw, p.y_m),
+                (p.x_nw, p.y_n),
+                (p.x_ne, p.y_n),
+                (p.x_fe, p.y_m),
+                (p.x_ne, p.y_s),
+                (p.x_nw, p.y_s),
+            ],
+        );
+    }
 
-        let canvas_width = 3. * self.width as f64 * a + a;
-        let canvas_height = 2. * self.height as f64 * b + b + 0.1 * cellsize;
+    fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
 
         let scalex = w.allocated_width() as f64 / canvas_width;
         let scaley = w.allocated_height() as f64 / canvas_height;
         cr.scale(scalex, scaley);
 
         for ix in 0..self.len() {
+            if !self.is_active(ix) {
+                continue;
+            }
             let cur_cell = self.cell(ix);
             let draw_line = |item: &Option<usize>, end: (f64, f64)| match item {
                 Some(r_idx) if (!cur_cell.links().contains(r_idx)) => cr.line_to(end.0, end.1),
@@ -206,6 +306,32 @@ impl GtkDrawable for HexagonalGrid {
         cr.restore().expect("error while restoring coords");
     }
 
+    fn draw_maze_inset(&self, w: &DrawingArea, cr: &Context, cellsize: f64, inset: f64) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+
+        for ix in (0..self.len()).filter(|&ix| self.is_active(ix)) {
+            let coords = hex_points(self.cells[ix].row, self.cells[ix].col, cellsize);
+            // clockwise from the north west corner, so side i is wall i of `walls`
+            let corners = [
+                (coords.x_nw, coords.y_n),
+                (coords.x_ne, coords.y_n),
+                (coords.x_fe, coords.y_m),
+                (coords.x_ne, coords.y_s),
+                (coords.x_nw, coords.y_s),
+                (coords.x_fw, coords.y_m),
+            ];
+            let open = self.walls(ix).map(|wall| !wall);
+            draw_utils::draw_inset_polygon(cr, &corners, (coords.cx, coords.cy), &open, inset);
+        }
+
+        cr.restore().expect("error while restoring coords");
+    }
+
     fn draw_pathfind(
         &self,
         w: &DrawingArea,
@@ -214,30 +340,15 @@ impl GtkDrawable for HexagonalGrid {
         cellsize: f64,
     ) {
         cr.save().expect("error while saving coords");
-        let a = cellsize / 2.;
-        let b = cellsize * 3f64.sqrt() / 2.;
-
-        let canvas_width = 3. * self.width as f64 * a + a;
-        let canvas_height = 2. * self.height as f64 * b + b + 0.1 * cellsize;
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
 
         let scalex = w.allocated_width() as f64 / canvas_width;
         let scaley = w.allocated_height() as f64 / canvas_height;
         cr.scale(scalex, scaley);
 
-        let mut max_idx = 0;
-        let mut min_idx = 0;
-        let mut max_length = step_state.cell_weights[max_idx].path_length;
-        let mut min_length = max_length;
-        for (i, c) in step_state.cell_weights.iter().enumerate() {
-            if c.path_length > max_length {
-                max_length = c.path_length;
-                max_idx = i;
-            }
-            if c.path_length < min_length {
-                min_length = c.path_length;
-                min_idx = i;
-            }
-        }
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let min_idx = step_state.path_to(max_idx)[0];
 
         let coords = |ix: usize| {
             let row = self.cell(ix).row();
@@ -246,6 +357,9 @@ impl GtkDrawable for HexagonalGrid {
         };
 
         for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 {
+                continue;
+            }
             let intensity = (max_length - c.path_length) as f64 / max_length as f64;
             let dark = intensity;
             let bright = 0.5 + intensity / 2.;
@@ -281,4 +395,32 @@ impl GtkDrawable for HexagonalGrid {
 
         cr.restore().expect("error while restoring coords");
     }
+
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let a = cellsize / 2.;
+        let b = cellsize * 3f64.sqrt() / 2.;
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let x = x * canvas_width / w.allocated_width() as f64;
+        let y = y * canvas_height / w.allocated_height() as f64;
+
+        // the closest centre is within one row and column of the estimate
+        let col = ((x - cellsize) / (3. * a)).round() as i64;
+        let mut best = None;
+        let mut best_dist = cellsize * cellsize;
+        for c in col - 1..=col + 1 {
+            let row = ((y - b - (c.rem_euclid(2) as f64) * b) / (2. * b)).round() as i64;
+            for r in row - 1..=row + 1 {
+                if r < 0 || c < 0 || self.ix_opt(r as usize, c as usize).is_none() {
+                    continue;
+                }
+                let (cx, cy) = center_coords(r as usize, c as usize, cellsize);
+                let dist = (cx - x).powi(2) + (cy - y).powi(2);
+                if dist <= best_dist {
+                    best_dist = dist;
+                    best = Some((r as usize, c as usize));
+                }
+            }
+        }
+        best
+    }
 }
@@ -1,4 +1,5 @@
 use draw_utils::{GenerationType, MazeType, Settings};
+use play::Move;
 use gtk::{prelude::*, Entry, RadioButton};
 use gtk::{ApplicationWindow, Button};
 use crate::gtk::prelude::ApplicationExt;
@@ -14,18 +15,36 @@ extern crate gio;
 extern crate gtk;
 
 
+mod bench;
+mod cli;
+mod codec;
+mod cube;
 mod delta;
+mod difficulty;
 mod draw_utils;
+mod export;
 mod generate;
 mod grid;
+mod grid3d;
 mod hexagonal;
+mod mask;
+mod play;
 mod polar;
+mod puzzle;
 mod rectangle;
 mod solve;
+mod sphere;
+mod stats;
+mod text;
+mod tiled;
+mod upsilon;
+mod weave;
 use gtk::Application;
-use solve::DijkstraStep;
-use std::sync::{Arc, RwLock};
-use std::time::Instant;
+use std::cell::Cell as StdCell;
+use std::rc::Rc;
+use std::sync::{mpsc, Arc, RwLock};
+use std::thread;
+use std::time::Duration;
 
 
 fn add_maze_option(
@@ -34,31 +53,15 @@ fn add_maze_option(
     switch_val: MazeType,
     button_name: &str,
     settings: Arc<RwLock<Settings>>,
+    size_spins: SizeSpins,
+    generators: GeneratorRadios,
 ) {
-    let bname_copy = String::from(button_name);
     let button = Button::with_label(button_name);
     button.connect_clicked(move |_| {
-        let now = Instant::now();
-        {
-            let mut real_settings = settings.write().unwrap();
-            real_settings.maze_type = switch_val.clone();
-            real_settings.make_maze();
-            draw_utils::draw_grid_mutex(
-                &img,
-                settings.clone(),
-                real_settings.generation_type.clone(),
-                real_settings.maze_type.clone(),
-                real_settings.braid_chance,
-                real_settings.version,
-            );
-        }
-        img.queue_draw();
-        let new_now = Instant::now();
-        println!(
-            "Click {:?} processed: {:?}",
-            bname_copy,
-            new_now.duration_since(now)
-        );
+        settings.write().unwrap().maze_type = switch_val.clone();
+        generators.show(&settings);
+        regenerate(&img, &settings);
+        size_spins.show(&settings);
     });
     container.add(&button);
 }
@@ -71,36 +74,634 @@ fn add_maze_generator(
     switch_val: GenerationType,
     button_name: &str,
     selected: bool,
-) {
+) -> RadioButton {
     let radio = RadioButton::with_label_from_widget(radio_group, button_name);
 
-    let bname_copy = String::from(button_name);
-
-    radio.connect_clicked(move |_| {
-        let now = Instant::now();
-        {
+    // the radio being switched off is clicked too
+    radio.connect_clicked(move |w| {
+        if !w.is_active() {
+            return;
+        }
+        let changed = {
             let mut real_settings = settings.write().unwrap();
+            let changed = real_settings.generation_type != switch_val;
             real_settings.generation_type = switch_val.clone();
-            real_settings.make_maze();
-            draw_utils::draw_grid_mutex(
-                &img,
-                settings.clone(),
-                real_settings.generation_type.clone(),
-                real_settings.maze_type.clone(),
-                real_settings.braid_chance,
-                real_settings.version,
-            );
+            changed
+        };
+        if changed {
+            regenerate(&img, &settings);
         }
-        img.queue_draw();
-        let new_now = Instant::now();
-        println!(
-            "Click {:?} processed: {:?}",
-            bname_copy,
-            new_now.duration_since(now)
-        );
     });
     radio.set_active(selected);
-    container.add(&radio)
+    container.add(&radio);
+    radio
+}
+
+/// Generator choices, some of which need a maze type with compass directions.
+#[derive(Clone)]
+struct GeneratorRadios {
+    backtracker: RadioButton,
+    compass: Vec<RadioButton>,
+}
+
+impl GeneratorRadios {
+    /// Disables the generators the current maze type can not use, moving off a disabled
+    /// one to the recursive backtracker. Like `SizeSpins::show`, this does not regenerate
+    /// the maze.
+    fn show(&self, settings: &Arc<RwLock<Settings>>) {
+        let (has_compass, moved) = {
+            let mut real_settings = settings.write().unwrap();
+            let has_compass = real_settings.maze_type.has_compass();
+            let moved = real_settings.generation_type.needs_compass() && !has_compass;
+            if moved {
+                real_settings.generation_type = GenerationType::RecursiveBacktracker;
+            }
+            (has_compass, moved)
+        };
+        if moved {
+            self.backtracker.set_active(true);
+        }
+        for radio in &self.compass {
+            radio.set_sensitive(has_compass);
+        }
+    }
+}
+
+/// Spin button for one of the ring layout options of polar mazes.
+fn add_polar_option(
+    img: gtk::DrawingArea,
+    container: &gtk::Box,
+    settings: Arc<RwLock<Settings>>,
+    label: &str,
+    spin: gtk::SpinButton,
+    apply: fn(&mut polar::PolarOptions, f64),
+) {
+    spin.connect_value_changed(move |w| {
+        apply(&mut settings.write().unwrap().polar, w.value());
+        regenerate(&img, &settings);
+    });
+    container.add(&gtk::Label::new(Some(label)));
+    container.add(&spin);
+}
+
+/// Rows and columns of the current maze type when it has no mask.
+#[derive(Clone)]
+struct SizeSpins {
+    rows: gtk::SpinButton,
+    cols: gtk::SpinButton,
+}
+
+impl SizeSpins {
+    /// Shows the size of the current maze type. The value handlers find the size unchanged,
+    /// so this does not regenerate the maze.
+    fn show(&self, settings: &Arc<RwLock<Settings>>) {
+        let ((rows, cols), has_columns) = {
+            let real_settings = settings.read().unwrap();
+            (real_settings.size(), real_settings.has_columns())
+        };
+        self.rows.set_value(rows as f64);
+        if has_columns {
+            self.cols.set_value(cols as f64);
+        }
+        self.cols.set_sensitive(has_columns);
+    }
+}
+
+fn add_size_options(
+    img: gtk::DrawingArea,
+    container: &gtk::Box,
+    settings: Arc<RwLock<Settings>>,
+) -> SizeSpins {
+    let (min, max) = (draw_utils::MIN_SIZE as f64, draw_utils::MAX_SIZE as f64);
+    let spins = SizeSpins {
+        rows: gtk::SpinButton::with_range(min, max, 1.),
+        cols: gtk::SpinButton::with_range(min, max, 1.),
+    };
+    spins.show(&settings);
+
+    let img_clone = img.clone();
+    let s_clone = settings.clone();
+    spins.rows.connect_value_changed(move |w| {
+        let changed = {
+            let mut real_settings = s_clone.write().unwrap();
+            let (_, cols) = real_settings.size();
+            real_settings.resize(w.value() as usize, cols)
+        };
+        if changed {
+            regenerate(&img_clone, &s_clone);
+        }
+    });
+    spins.cols.connect_value_changed(move |w| {
+        let changed = {
+            let mut real_settings = settings.write().unwrap();
+            let (rows, _) = real_settings.size();
+            real_settings.resize(rows, w.value() as usize)
+        };
+        if changed {
+            regenerate(&img, &settings);
+        }
+    });
+
+    container.add(&gtk::Label::new(Some("rows (rings, cube face size)")));
+    container.add(&spins.rows);
+    container.add(&gtk::Label::new(Some("columns")));
+    container.add(&spins.cols);
+    spins
+}
+
+/// Generates and solves a maze for the current settings on a worker thread, cancelling the
+/// run still going, and shows it once done.
+fn regenerate(img: &gtk::DrawingArea, settings: &Arc<RwLock<Settings>>) {
+    let progress = Arc::new(generate::Progress::default());
+    let request = {
+        let mut real_settings = settings.write().unwrap();
+        real_settings.progress.cancel();
+        real_settings.progress = progress.clone();
+        real_settings.request()
+    };
+
+    let (sender, receiver) = mpsc::channel();
+    let worker_progress = progress.clone();
+    thread::spawn(move || {
+        let maze = request.generate(&worker_progress);
+        // the receiver is only gone if the main loop stopped
+        let _ = sender.send(maze);
+    });
+
+    let img = img.clone();
+    let settings = settings.clone();
+    gtk::glib::timeout_add_local(Duration::from_millis(50), move || {
+        let maze = match receiver.try_recv() {
+            Ok(maze) => maze,
+            Err(mpsc::TryRecvError::Empty) => return gtk::glib::ControlFlow::Continue,
+            Err(mpsc::TryRecvError::Disconnected) => return gtk::glib::ControlFlow::Break,
+        };
+        let mut real_settings = settings.write().unwrap();
+        // a newer run or the mask editor took over while this one was going
+        let current = Arc::ptr_eq(&real_settings.progress, &progress) && !progress.is_cancelled();
+        if let Some(maze) = maze.filter(|_| current && !real_settings.editing_mask) {
+            real_settings.install_maze(maze);
+            img.queue_draw();
+        }
+        gtk::glib::ControlFlow::Break
+    });
+}
+
+/// Progress bar of the running generator, counting carved cells, with a button to cancel it.
+fn add_progress_bar(container: &gtk::Box, settings: Arc<RwLock<Settings>>) {
+    let bar = gtk::ProgressBar::new();
+    bar.set_show_text(true);
+    let cancel_button = Button::with_label("cancel generation");
+
+    let s_clone = settings.clone();
+    cancel_button.connect_clicked(move |_| {
+        s_clone.read().unwrap().progress.cancel();
+    });
+
+    let bar_clone = bar.clone();
+    let button_clone = cancel_button.clone();
+    gtk::glib::timeout_add_local(Duration::from_millis(100), move || {
+        let progress = settings.read().unwrap().progress.clone();
+        let running = !progress.is_finished();
+        let (carved, total) = (progress.carved(), progress.total());
+        if running && total > 0 {
+            bar_clone.set_fraction((carved as f64 / total as f64).min(1.));
+            bar_clone.set_text(Some(&format!("{} / {} cells", carved, total)));
+        } else {
+            bar_clone.set_fraction(0.);
+            bar_clone.set_text(Some(if running { "solving" } else { "done" }));
+        }
+        button_clone.set_sensitive(running && !progress.is_cancelled());
+        gtk::glib::ControlFlow::Continue
+    });
+
+    container.add(&bar);
+    container.add(&cancel_button);
+}
+
+/// Shows `message` in a modal dialog on top of `window`. Runs a nested main loop, so callers
+/// must not hold the settings lock.
+fn show_error(window: &ApplicationWindow, message: &str) {
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL,
+        gtk::MessageType::Error,
+        gtk::ButtonsType::Close,
+        message,
+    );
+    dialog.run();
+    dialog.close();
+}
+
+fn choose_mask_file(
+    window: &ApplicationWindow,
+    action: gtk::FileChooserAction,
+    title: &str,
+) -> Option<std::path::PathBuf> {
+    let accept = if action == gtk::FileChooserAction::Save {
+        "Save"
+    } else {
+        "Open"
+    };
+    let dialog = gtk::FileChooserDialog::with_buttons(
+        Some(title),
+        Some(window),
+        action,
+        &[
+            ("Cancel", gtk::ResponseType::Cancel),
+            (accept, gtk::ResponseType::Accept),
+        ],
+    );
+    dialog.set_do_overwrite_confirmation(true);
+    let res = if dialog.run() == gtk::ResponseType::Accept {
+        dialog.filename()
+    } else {
+        None
+    };
+    dialog.close();
+    res
+}
+
+// While "edit mask" is on, the maze is replaced by an empty grid and clicking or dragging
+// over it toggles cells; "generate" builds a maze in the enabled cells.
+fn add_mask_editor(
+    img: gtk::DrawingArea,
+    window: &ApplicationWindow,
+    container: &gtk::Box,
+    settings: Arc<RwLock<Settings>>,
+) {
+    let edit_button = gtk::ToggleButton::with_label("edit mask");
+    let generate_button = Button::with_label("generate");
+    let clear_button = Button::with_label("clear mask");
+    let shape_button = Button::with_label("hexagon / triangle");
+    let save_button = Button::with_label("save mask");
+    let load_button = Button::with_label("load mask");
+
+    let img_clone = img.clone();
+    let s_clone = settings.clone();
+    edit_button.connect_toggled(move |w| {
+        if w.is_active() {
+            let mut real_settings = s_clone.write().unwrap();
+            real_settings.editing_mask = true;
+            real_settings.show_mask();
+            img_clone.queue_draw();
+        } else {
+            s_clone.write().unwrap().editing_mask = false;
+            regenerate(&img_clone, &s_clone);
+        }
+    });
+
+    let img_clone = img.clone();
+    let s_clone = settings.clone();
+    let edit_clone = edit_button.clone();
+    generate_button.connect_clicked(move |_| {
+        if edit_clone.is_active() {
+            // the toggle handler generates the maze
+            edit_clone.set_active(false);
+        } else {
+            regenerate(&img_clone, &s_clone);
+        }
+    });
+
+    let img_clone = img.clone();
+    let s_clone = settings.clone();
+    clear_button.connect_clicked(move |_| {
+        let editing = {
+            let mut real_settings = s_clone.write().unwrap();
+            real_settings.mask = None;
+            if real_settings.editing_mask {
+                real_settings.show_mask();
+            }
+            real_settings.editing_mask
+        };
+        if editing {
+            img_clone.queue_draw();
+        } else {
+            regenerate(&img_clone, &s_clone);
+        }
+    });
+
+    let img_clone = img.clone();
+    let window_clone = window.clone();
+    let s_clone = settings.clone();
+    shape_button.connect_clicked(move |_| {
+        let editing = {
+            let mut real_settings = s_clone.write().unwrap();
+            let Some(mask) = real_settings.shape_mask() else {
+                drop(real_settings);
+                show_error(&window_clone, "Only hex and delta mazes have a built-in shape");
+                return;
+            };
+            real_settings.mask = Some(mask);
+            if real_settings.editing_mask {
+                real_settings.show_mask();
+            }
+            real_settings.editing_mask
+        };
+        if editing {
+            img_clone.queue_draw();
+        } else {
+            regenerate(&img_clone, &s_clone);
+        }
+    });
+
+    let window_clone = window.clone();
+    let s_clone = settings.clone();
+    save_button.connect_clicked(move |_| {
+        let path = choose_mask_file(&window_clone, gtk::FileChooserAction::Save, "Save mask");
+        if let Some(path) = path {
+            let mask = {
+                let real_settings = s_clone.read().unwrap();
+                match &real_settings.mask {
+                    Some(mask) => mask.clone(),
+                    None => {
+                        let (rows, cols) = real_settings.size();
+                        mask::Mask::new(rows, cols)
+                    }
+                }
+            };
+            if let Err(e) = mask.save(&path) {
+                show_error(&window_clone, &format!("Could not save mask: {}", e));
+            }
+        }
+    });
+
+    let img_clone = img.clone();
+    let window_clone = window.clone();
+    let s_clone = settings.clone();
+    load_button.connect_clicked(move |_| {
+        let path = choose_mask_file(&window_clone, gtk::FileChooserAction::Open, "Load mask");
+        let Some(path) = path else {
+            return;
+        };
+        let mask = match mask::Mask::from_file(&path) {
+            Ok(mask) => mask,
+            Err(e) => {
+                show_error(&window_clone, &format!("Could not load mask: {}", e));
+                return;
+            }
+        };
+        let editing = {
+            let mut real_settings = s_clone.write().unwrap();
+            real_settings.mask = Some(mask);
+            if real_settings.editing_mask {
+                real_settings.show_mask();
+            }
+            real_settings.editing_mask
+        };
+        if editing {
+            img_clone.queue_draw();
+        } else {
+            regenerate(&img_clone, &s_clone);
+        }
+    });
+
+    // The state set by the first cell of a drag is applied to every cell dragged over.
+    let paint_state = Rc::new(StdCell::new(true));
+    img.add_events(
+        gtk::gdk::EventMask::BUTTON_PRESS_MASK | gtk::gdk::EventMask::BUTTON1_MOTION_MASK,
+    );
+
+    let s_clone = settings.clone();
+    let state_clone = paint_state.clone();
+    img.connect_button_press_event(move |w, ev| {
+        let mut real_settings = s_clone.write().unwrap();
+        if !real_settings.editing_mask || ev.button() != 1 {
+            return gtk::glib::Propagation::Proceed;
+        }
+        let (x, y) = ev.position();
+        if let Some(on) = real_settings.paint_mask(w, x, y, None) {
+            state_clone.set(on);
+            w.queue_draw();
+        }
+        gtk::glib::Propagation::Stop
+    });
+
+    let s_clone = settings.clone();
+    img.connect_motion_notify_event(move |w, ev| {
+        let mut real_settings = s_clone.write().unwrap();
+        let dragging = ev.state().contains(gtk::gdk::ModifierType::BUTTON1_MASK);
+        if !real_settings.editing_mask || !dragging {
+            return gtk::glib::Propagation::Proceed;
+        }
+        let (x, y) = ev.position();
+        if real_settings
+            .paint_mask(w, x, y, Some(paint_state.get()))
+            .is_some()
+        {
+            w.queue_draw();
+        }
+        gtk::glib::Propagation::Stop
+    });
+
+    container.add(&edit_button);
+    container.add(&generate_button);
+    container.add(&clear_button);
+    container.add(&shape_button);
+    container.add(&save_button);
+    container.add(&load_button);
+}
+
+/// Side panel with the statistics of the current maze, see `stats::MazeStats`.
+fn add_stats_panel(container: &gtk::Box, settings: Arc<RwLock<Settings>>) {
+    let frame = gtk::Frame::new(Some("maze statistics"));
+    let label = gtk::Label::new(None);
+    label.set_xalign(0.);
+    label.set_yalign(0.);
+    label.set_margin(5);
+    frame.add(&label);
+
+    let shown = StdCell::new(None);
+    gtk::glib::timeout_add_local(Duration::from_millis(200), move || {
+        let settings = settings.read().unwrap();
+        if shown.get() != Some(settings.version) {
+            shown.set(Some(settings.version));
+            match &settings.stats {
+                Some(stats) => label.set_text(&stats.to_string()),
+                None => label.set_text(""),
+            }
+        }
+        gtk::glib::ControlFlow::Continue
+    });
+
+    container.add(&frame);
+}
+
+/// Zoom factor of one mouse wheel step.
+const ZOOM_STEP: f64 = 1.25;
+
+/// Mouse wheel zoom, panning by dragging the maze (with the middle button while editing the
+/// mask) and a button fitting the whole maze back into view.
+fn add_view_controls(
+    img: gtk::DrawingArea,
+    container: &gtk::Box,
+    settings: Arc<RwLock<Settings>>,
+) {
+    img.add_events(
+        gtk::gdk::EventMask::SCROLL_MASK
+            | gtk::gdk::EventMask::SMOOTH_SCROLL_MASK
+            | gtk::gdk::EventMask::BUTTON_PRESS_MASK
+            | gtk::gdk::EventMask::BUTTON_RELEASE_MASK
+            | gtk::gdk::EventMask::BUTTON1_MOTION_MASK
+            | gtk::gdk::EventMask::BUTTON2_MOTION_MASK,
+    );
+
+    let s_clone = settings.clone();
+    img.connect_scroll_event(move |w, ev| {
+        let factor = match ev.direction() {
+            gtk::gdk::ScrollDirection::Up => ZOOM_STEP,
+            gtk::gdk::ScrollDirection::Down => 1. / ZOOM_STEP,
+            gtk::gdk::ScrollDirection::Smooth => ZOOM_STEP.powf(-ev.delta().1),
+            _ => return gtk::glib::Propagation::Proceed,
+        };
+        let (x, y) = ev.position();
+        let (width, height) = (w.allocated_width() as f64, w.allocated_height() as f64);
+        s_clone.write().unwrap().zoom_at(factor, x, y, width, height);
+        w.queue_draw();
+        gtk::glib::Propagation::Stop
+    });
+
+    // where the pointer was when the drag last moved the maze
+    let drag_from = Rc::new(StdCell::new(None));
+
+    let s_clone = settings.clone();
+    let drag_clone = drag_from.clone();
+    img.connect_button_press_event(move |_, ev| {
+        let editing = s_clone.read().unwrap().editing_mask;
+        if ev.button() == 2 || (ev.button() == 1 && !editing) {
+            drag_clone.set(Some(ev.position()));
+        }
+        gtk::glib::Propagation::Proceed
+    });
+
+    let drag_clone = drag_from.clone();
+    img.connect_button_release_event(move |_, _| {
+        drag_clone.set(None);
+        gtk::glib::Propagation::Proceed
+    });
+
+    let s_clone = settings.clone();
+    img.connect_motion_notify_event(move |w, ev| {
+        let Some((from_x, from_y)) = drag_from.get() else {
+            return gtk::glib::Propagation::Proceed;
+        };
+        let (x, y) = ev.position();
+        {
+            let mut real_settings = s_clone.write().unwrap();
+            real_settings.view.pan.0 += x - from_x;
+            real_settings.view.pan.1 += y - from_y;
+        }
+        drag_from.set(Some((x, y)));
+        w.queue_draw();
+        gtk::glib::Propagation::Stop
+    });
+
+    let fit_button = Button::with_label("fit maze to view");
+    fit_button.connect_clicked(move |_| {
+        settings.write().unwrap().view = draw_utils::View::default();
+        img.queue_draw();
+    });
+    container.add(&fit_button);
+}
+
+/// Keys that move the player, see `play::Move`.
+fn player_move(key: &gtk::gdk::keys::Key) -> Option<Move> {
+    use gtk::gdk::keys::constants as keys;
+    [
+        (keys::Up, Move::North),
+        (keys::Right, Move::East),
+        (keys::Down, Move::South),
+        (keys::Left, Move::West),
+        (keys::q, Move::NorthWest),
+        (keys::e, Move::NorthEast),
+        (keys::a, Move::SouthWest),
+        (keys::d, Move::SouthEast),
+        (keys::Page_Up, Move::Up),
+        (keys::Page_Down, Move::Down),
+    ]
+    .into_iter()
+    .find(|(k, _)| *k == key.to_lower())
+    .map(|(_, m)| m)
+}
+
+/// Button playing the maze with the keyboard, fog-of-war and locked doors options, and a
+/// label with the moves, keys and time so far.
+fn add_play_controls(
+    img: gtk::DrawingArea,
+    window: &ApplicationWindow,
+    container: &gtk::Box,
+    settings: Arc<RwLock<Settings>>,
+) {
+    let play_button = gtk::ToggleButton::with_label("play maze");
+    let fog_button = gtk::CheckButton::with_label("fog of war");
+    let doors_label = gtk::Label::new(Some("locked doors on the solution"));
+    let doors_spin = gtk::SpinButton::with_range(0., puzzle::MAX_DOORS as f64, 1.);
+    let status = gtk::Label::new(None);
+
+    let img_clone = img.clone();
+    let s_clone = settings.clone();
+    play_button.connect_toggled(move |w| {
+        s_clone.write().unwrap().set_playing(w.is_active());
+        img_clone.queue_draw();
+    });
+
+    let img_clone = img.clone();
+    let s_clone = settings.clone();
+    fog_button.connect_toggled(move |w| {
+        s_clone.write().unwrap().set_fog(w.is_active());
+        img_clone.queue_draw();
+    });
+
+    let img_clone = img.clone();
+    let s_clone = settings.clone();
+    doors_spin.connect_value_changed(move |w| {
+        s_clone.write().unwrap().doors = w.value() as usize;
+        regenerate(&img_clone, &s_clone);
+    });
+
+    let s_clone = settings.clone();
+    window.connect_key_press_event(move |window, ev| {
+        // keys typed into entries are not moves
+        if window.focused_widget().is_some_and(|w| w.is::<Entry>()) {
+            return gtk::glib::Propagation::Proceed;
+        }
+        let Some(towards) = player_move(&ev.keyval()) else {
+            return gtk::glib::Propagation::Proceed;
+        };
+        let mut real_settings = s_clone.write().unwrap();
+        let real_settings = &mut *real_settings;
+        let Some(game) = &mut real_settings.game else {
+            return gtk::glib::Propagation::Proceed;
+        };
+        if game.try_move(&*real_settings.grid, towards) {
+            img.queue_draw();
+        }
+        gtk::glib::Propagation::Stop
+    });
+
+    let status_clone = status.clone();
+    gtk::glib::timeout_add_local(Duration::from_millis(100), move || {
+        let text = match &settings.read().unwrap().game {
+            Some(game) => {
+                let seconds = game.elapsed().as_secs_f64();
+                let mut text = format!("{} moves, {:.1} s", game.moves, seconds);
+                if let Some(puzzle) = &game.puzzle {
+                    let held = game.keys.count_ones();
+                    text += &format!(", {} of {} keys", held, puzzle.keys.len());
+                }
+                text
+            }
+            None => String::new(),
+        };
+        status_clone.set_text(&text);
+        gtk::glib::ControlFlow::Continue
+    });
+
+    container.add(&play_button);
+    container.add(&fog_button);
+    container.add(&doors_label);
+    container.add(&doors_spin);
+    container.add(&status);
 }
 
 fn create_gtk_app() {
@@ -116,7 +717,9 @@ fn create_gtk_app() {
 
         let img = gtk::DrawingArea::new();
         img.set_size_request(400, 400);
-        container.add(&img);
+        let maze_row = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        maze_row.add(&img);
+        container.add(&maze_row);
         container.add(&buttons_container);
         buttons_container.add(&maze_buttons);
         buttons_container.add(&radio_container);
@@ -124,16 +727,76 @@ fn create_gtk_app() {
         img.set_vexpand(true);
         img.set_hexpand(true);
 
-        let f = || Box::new(rectangle::RegularGrid::new(70, 70));
-        let settings = Arc::new(RwLock::new(Settings {
-            maze_type: MazeType::Regular,
-            generation_type: GenerationType::RecursiveBacktracker,
-            braid_chance: 0,
-            step: DijkstraStep::initial(&rectangle::RegularGrid::new(70, 70), 0),
-            version: 0,
-            drawn: false,
-            grid: f(),
-        }));
+        let settings = Arc::new(RwLock::new(Settings::default()));
+        let backtracker = add_maze_generator(
+            img.clone(),
+            &radio_container,
+            &radio_button,
+            settings.clone(),
+            GenerationType::RecursiveBacktracker,
+            "Recursive Backtracker",
+            true,
+        );
+        add_maze_generator(
+            img.clone(),
+            &radio_container,
+            &radio_button,
+            settings.clone(),
+            GenerationType::AldousBroder,
+            "Aldous Broder",
+            false,
+        );
+        add_maze_generator(
+            img.clone(),
+            &radio_container,
+            &radio_button,
+            settings.clone(),
+            GenerationType::SimplifiedPrim,
+            "Simplified Prim",
+            false,
+        );
+        add_maze_generator(
+            img.clone(),
+            &radio_container,
+            &radio_button,
+            settings.clone(),
+            GenerationType::TruePrim,
+            "True Prim",
+            false,
+        );
+        add_maze_generator(
+            img.clone(),
+            &radio_container,
+            &radio_button,
+            settings.clone(),
+            GenerationType::Kruskal,
+            "Kruskal",
+            false,
+        );
+        let binary_tree = add_maze_generator(
+            img.clone(),
+            &radio_container,
+            &radio_button,
+            settings.clone(),
+            GenerationType::BinaryTree,
+            "Binary Tree",
+            false,
+        );
+        let sidewinder = add_maze_generator(
+            img.clone(),
+            &radio_container,
+            &radio_button,
+            settings.clone(),
+            GenerationType::Sidewinder,
+            "Sidewinder",
+            false,
+        );
+        let generators = GeneratorRadios {
+            backtracker,
+            compass: vec![binary_tree, sidewinder],
+        };
+
+        let size_spins = add_size_options(img.clone(), &maze_buttons, settings.clone());
 
         add_maze_option(
             img.clone(),
@@ -141,6 +804,8 @@ fn create_gtk_app() {
             MazeType::Regular,
             "draw rectangle maze",
             settings.clone(),
+            size_spins.clone(),
+            generators.clone(),
         );
         add_maze_option(
             img.clone(),
@@ -148,6 +813,8 @@ fn create_gtk_app() {
             MazeType::Circular,
             "draw polar maze",
             settings.clone(),
+            size_spins.clone(),
+            generators.clone(),
         );
         add_maze_option(
             img.clone(),
@@ -155,6 +822,8 @@ fn create_gtk_app() {
             MazeType::Hexagonal,
             "draw hex maze",
             settings.clone(),
+            size_spins.clone(),
+            generators.clone(),
         );
         add_maze_option(
             img.clone(),
@@ -162,43 +831,53 @@ fn create_gtk_app() {
             MazeType::Delta,
             "draw delta maze",
             settings.clone(),
+            size_spins.clone(),
+            generators.clone(),
         );
-
-        add_maze_generator(
+        add_maze_option(
             img.clone(),
-            &radio_container,
-            &radio_button,
+            &maze_buttons,
+            MazeType::Upsilon,
+            "draw upsilon maze",
             settings.clone(),
-            GenerationType::RecursiveBacktracker,
-            "Recursive Backtracker",
-            true,
+            size_spins.clone(),
+            generators.clone(),
         );
-        add_maze_generator(
+        add_maze_option(
             img.clone(),
-            &radio_container,
-            &radio_button,
+            &maze_buttons,
+            MazeType::Weave,
+            "draw weave maze",
             settings.clone(),
-            GenerationType::AldousBroder,
-            "Aldous Broder",
-            false,
+            size_spins.clone(),
+            generators.clone(),
         );
-        add_maze_generator(
+        add_maze_option(
             img.clone(),
-            &radio_container,
-            &radio_button,
+            &maze_buttons,
+            MazeType::ThreeD,
+            "draw 3D maze",
             settings.clone(),
-            GenerationType::SimplifiedPrim,
-            "Simplified Prim",
-            false,
+            size_spins.clone(),
+            generators.clone(),
         );
-        add_maze_generator(
+        add_maze_option(
             img.clone(),
-            &radio_container,
-            &radio_button,
+            &maze_buttons,
+            MazeType::Cube,
+            "draw cube maze",
             settings.clone(),
-            GenerationType::TruePrim,
-            "True Prim",
-            false,
+            size_spins.clone(),
+            generators.clone(),
+        );
+        add_maze_option(
+            img.clone(),
+            &maze_buttons,
+            MazeType::Sphere,
+            "draw sphere maze",
+            settings.clone(),
+            size_spins.clone(),
+            generators.clone(),
         );
 
         let entry = Entry::new();
@@ -208,42 +887,138 @@ fn create_gtk_app() {
         let img_clone = img.clone();
         let s_clone = settings.clone();
         entry.connect_changed(move |w| {
-            let now = Instant::now();
             let num = w.text().parse::<u8>().unwrap_or_default();
-            {
-                let mut real_settings = s_clone.write().unwrap();
+            s_clone.write().unwrap().braid_chance = num;
+            regenerate(&img_clone, &s_clone);
+        });
 
-                real_settings.braid_chance = num;
-
-                real_settings.make_maze();
-                draw_utils::draw_grid_mutex(
-                    &img_clone,
-                    s_clone.clone(),
-                    real_settings.generation_type.clone(),
-                    real_settings.maze_type.clone(),
-                    real_settings.braid_chance,
-                    real_settings.version,
-                );
+        draw_utils::connect_maze_drawing(&img, settings.clone());
+        add_progress_bar(&container, settings.clone());
+        add_stats_panel(&maze_row, settings.clone());
+        regenerate(&img, &settings);
+        // entry.(b)
+        radio_container.add(&entry);
+
+        let code_entry = Entry::new();
+        code_entry.set_placeholder_text(Some("maze code"));
+        let copy_button = Button::with_label("copy maze code");
+        let load_button = Button::with_label("load maze code");
+
+        let entry_clone = code_entry.clone();
+        let window_clone = window.clone();
+        let s_clone = settings.clone();
+        copy_button.connect_clicked(move |_| {
+            let code = s_clone.read().unwrap().code.clone();
+            if code.is_empty() {
+                show_error(&window_clone, "This maze has no code");
+                return;
             }
+            entry_clone.set_text(&code);
+            gtk::Clipboard::get(&gtk::gdk::SELECTION_CLIPBOARD).set_text(&code);
+        });
+
+        let img_clone = img.clone();
+        let entry_clone = code_entry.clone();
+        let window_clone = window.clone();
+        let s_clone = settings.clone();
+        load_button.connect_clicked(move |_| {
+            let loaded = {
+                let mut real_settings = s_clone.write().unwrap();
+                real_settings.progress.cancel();
+                real_settings.load_code(&entry_clone.text())
+            };
+            if let Err(e) = loaded {
+                show_error(&window_clone, &format!("Could not load maze code: {}", e));
+                return;
+            }
+            size_spins.show(&s_clone);
+            generators.show(&s_clone);
             img_clone.queue_draw();
-            let new_now = Instant::now();
-            println!("Braid chance  processed: {:?}", new_now.duration_since(now));
         });
+        radio_container.add(&code_entry);
+        radio_container.add(&copy_button);
+        radio_container.add(&load_button);
 
-        {
-            let mut real_settings = settings.write().unwrap();
-            real_settings.make_maze();
-            draw_utils::draw_grid_mutex(
-                &img.clone(),
-                settings.clone(),
-                real_settings.generation_type.clone(),
-                real_settings.maze_type.clone(),
-                real_settings.braid_chance,
-                real_settings.version,
-            );
+        add_mask_editor(img.clone(), &window, &radio_container, settings.clone());
+        add_view_controls(img.clone(), &radio_container, settings.clone());
+        add_play_controls(img.clone(), &window, &radio_container, settings.clone());
+
+        // 0 keeps walls on the cell borders, larger values narrow the passages
+        let inset_label = gtk::Label::new(Some("wall inset"));
+        let inset_scale = gtk::Scale::with_range(gtk::Orientation::Horizontal, 0., 0.8, 0.05);
+        inset_scale.set_value(0.);
+        let img_clone = img.clone();
+        let s_clone = settings.clone();
+        inset_scale.connect_value_changed(move |w| {
+            let mut real_settings = s_clone.write().unwrap();
+            real_settings.inset = w.value();
+            real_settings.invalidate();
+            img_clone.queue_draw();
+        });
+        radio_container.add(&inset_label);
+        radio_container.add(&inset_scale);
+
+        // edges of rectangle mazes that join up
+        let wrap_combo = gtk::ComboBoxText::new();
+        for (id, label) in [
+            ("none", "no wrapping"),
+            ("cylinder", "cylinder"),
+            ("torus", "torus"),
+            ("moebius", "Möbius strip"),
+        ] {
+            wrap_combo.append(Some(id), label);
         }
-        // entry.(b)
-        radio_container.add(&entry);
+        wrap_combo.set_active_id(Some("none"));
+        let img_clone = img.clone();
+        let s_clone = settings.clone();
+        wrap_combo.connect_changed(move |w| {
+            let wrap = match w.active_id().as_deref() {
+                Some("cylinder") => rectangle::Wrap::Cylinder,
+                Some("torus") => rectangle::Wrap::Torus,
+                Some("moebius") => rectangle::Wrap::Moebius,
+                _ => rectangle::Wrap::None,
+            };
+            s_clone.write().unwrap().wrap = wrap;
+            regenerate(&img_clone, &s_clone);
+        });
+        let tile_button = gtk::CheckButton::with_label("tile wrapped maze");
+        let img_clone = img.clone();
+        let s_clone = settings.clone();
+        tile_button.connect_toggled(move |w| {
+            let mut real_settings = s_clone.write().unwrap();
+            real_settings.tiled = w.is_active();
+            real_settings.invalidate();
+            img_clone.queue_draw();
+        });
+        radio_container.add(&wrap_combo);
+        radio_container.add(&tile_button);
+
+        add_polar_option(
+            img.clone(),
+            &radio_container,
+            settings.clone(),
+            "polar hub rings",
+            gtk::SpinButton::with_range(0., 20., 1.),
+            |options, v| options.hub = v as usize,
+        );
+        add_polar_option(
+            img.clone(),
+            &radio_container,
+            settings.clone(),
+            "first ring cells (0: automatic)",
+            gtk::SpinButton::with_range(0., 64., 1.),
+            |options, v| options.first_ring = Some(v as usize).filter(|&n| n > 0),
+        );
+        let aspect_spin = gtk::SpinButton::with_range(1., 4., 0.1);
+        aspect_spin.set_value(polar::PolarOptions::default().max_aspect);
+        add_polar_option(
+            img.clone(),
+            &radio_container,
+            settings.clone(),
+            "max polar cell aspect",
+            aspect_spin,
+            |options, v| options.max_aspect = v,
+        );
 
         window.add(&container);
         window.show_all();
@@ -252,5 +1027,11 @@ fn create_gtk_app() {
 }
 
 fn main() {
-    create_gtk_app();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() {
+        create_gtk_app();
+    } else if let Err(e) = cli::run(&args) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
 }
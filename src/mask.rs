@@ -0,0 +1,101 @@
+use std::path::Path;
+
+/// Which cells of a rows x cols grid take part in the maze.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mask {
+    pub rows: usize,
+    pub cols: usize,
+    bits: Vec<bool>,
+}
+
+impl Mask {
+    pub fn new(rows: usize, cols: usize) -> Mask {
+        Mask {
+            rows,
+            cols,
+            bits: vec![true; rows * cols],
+        }
+    }
+
+    /// One line per row, `X` (or `x`) marks a disabled cell and any other character an
+    /// enabled one. Short lines are padded with enabled cells.
+    pub fn from_text(text: &str) -> Mask {
+        let lines: Vec<&str> = text.trim_end_matches(['\n', '\r']).lines().collect();
+        let cols = lines.iter().map(|l| l.chars().count()).max().unwrap_or(0);
+        let mut mask = Mask::new(lines.len(), cols);
+        for (row, line) in lines.iter().enumerate() {
+            for (col, c) in line.chars().enumerate() {
+                mask.set(row, col, c != 'X' && c != 'x');
+            }
+        }
+        mask
+    }
+
+    /// One cell per pixel, dark pixels disable cells.
+    pub fn from_image(img: &image::DynamicImage) -> Mask {
+        let img = img.to_luma8();
+        let mut mask = Mask::new(img.height() as usize, img.width() as usize);
+        for (x, y, p) in img.enumerate_pixels() {
+            mask.set(y as usize, x as usize, p.0[0] >= 128);
+        }
+        mask
+    }
+
+    /// Reads a mask from a PNG (or any image `image` can open) or, for `.txt` files, text.
+    pub fn from_file(path: &Path) -> Result<Mask, String> {
+        let err = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        let mask = if path.extension().is_some_and(|ext| ext == "txt") {
+            Mask::from_text(&std::fs::read_to_string(path).map_err(|e| err(&e))?)
+        } else {
+            Mask::from_image(&image::open(path).map_err(|e| err(&e))?)
+        };
+        if mask.count() == 0 {
+            return Err(err(&"mask has no enabled cells"));
+        }
+        Ok(mask)
+    }
+
+    /// Writes the mask as text, or as a black and white image for any other extension.
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let err = |e: &dyn std::fmt::Display| format!("{}: {}", path.display(), e);
+        if path.extension().is_some_and(|ext| ext == "txt") {
+            std::fs::write(path, self.to_text()).map_err(|e| err(&e))
+        } else {
+            self.to_image().save(path).map_err(|e| err(&e))
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut res = String::new();
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                res.push(if self.is_on(row, col) { '.' } else { 'X' });
+            }
+            res.push('\n');
+        }
+        res
+    }
+
+    pub fn to_image(&self) -> image::GrayImage {
+        image::GrayImage::from_fn(self.cols as u32, self.rows as u32, |x, y| {
+            image::Luma([if self.is_on(y as usize, x as usize) {
+                255
+            } else {
+                0
+            }])
+        })
+    }
+
+    pub fn is_on(&self, row: usize, col: usize) -> bool {
+        row < self.rows && col < self.cols && self.bits[col + row * self.cols]
+    }
+
+    pub fn set(&mut self, row: usize, col: usize, on: bool) {
+        self.bits[col + row * self.cols] = on;
+    }
+
+    /// Number of enabled cells.
+    pub fn count(&self) -> usize {
+        self.bits.iter().filter(|&&b| b).count()
+    }
+}
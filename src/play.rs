@@ -0,0 +1,344 @@
+use crate::draw_utils::GtkDrawable;
+use crate::grid::{AbstractCell, AbstractGrid, CompassDirections};
+use crate::polar::PolarCell;
+use crate::puzzle::Puzzle;
+use crate::solve::DijkstraStep;
+use gtk::cairo::{Context, Operator};
+use std::collections::HashSet;
+use std::f64::consts::PI;
+use std::time::{Duration, Instant};
+
+/// Trail segments longer than this many cells jump between parts of the drawing (levels,
+/// faces of the cube net, hemispheres) and are left out.
+pub const MAX_TRAIL_STEP: f64 = 2.5;
+
+/// Where a key asks the player to go. Each grid gives these the meaning that fits its
+/// cells, see `GtkDrawable::player_move`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Move {
+    North,
+    East,
+    South,
+    West,
+    NorthEast,
+    NorthWest,
+    SouthEast,
+    SouthWest,
+    /// One level up or down in 3D mazes.
+    Up,
+    Down,
+}
+
+/// Every move, for looking around in all directions.
+const MOVES: [Move; 10] = [
+    Move::North,
+    Move::East,
+    Move::South,
+    Move::West,
+    Move::NorthEast,
+    Move::NorthWest,
+    Move::SouthEast,
+    Move::SouthWest,
+    Move::Up,
+    Move::Down,
+];
+
+/// How many steps along passages the player sees round corners in fog-of-war mode.
+const SIGHT_RADIUS: usize = 2;
+
+/// Moves along the passages of grids with compass directions.
+pub fn compass_move<C: AbstractCell, G: CompassDirections + AbstractGrid<C>>(
+    g: &G,
+    ix: usize,
+    towards: Move,
+) -> Option<usize> {
+    let target = match towards {
+        Move::North => g.north_ix(ix),
+        Move::East => g.east_ix(ix),
+        Move::South => g.south_ix(ix),
+        Move::West => g.west_ix(ix),
+        _ => None,
+    };
+    target.filter(|n| g.links(ix).contains(n))
+}
+
+/// Moves in polar and sphere mazes: north goes outward to the linked cell straight out,
+/// north west and north east to the counter-clockwise-most and clockwise-most of several
+/// linked outer cells, south inward, east clockwise and west counter-clockwise. From the
+/// middle cell every move goes outward, to the cell that lies that way on the screen.
+pub fn polar_move(cells: &[PolarCell], ix: usize, towards: Move) -> Option<usize> {
+    let cell = &cells[ix];
+    // middle of a cell as a fraction of a turn, clockwise from east
+    let angle = |c: &PolarCell| (c.col as f64 + 0.5) / c.columns as f64;
+    let turn = |a: f64, n: usize| {
+        let d = (angle(&cells[n]) - a).rem_euclid(1.);
+        d.min(1. - d)
+    };
+    let outward = cell.outward.iter().copied().filter(|n| cell.links.contains(n));
+    if cell.columns == 1 {
+        let a = match towards {
+            Move::East => 0.,
+            Move::SouthEast => 0.125,
+            Move::South => 0.25,
+            Move::SouthWest => 0.375,
+            Move::West => 0.5,
+            Move::NorthWest => 0.625,
+            Move::North => 0.75,
+            Move::NorthEast => 0.875,
+            _ => return None,
+        };
+        return outward.min_by(|&m, &n| turn(a, m).total_cmp(&turn(a, n)));
+    }
+    let straight = angle(cell);
+    let target = match towards {
+        Move::North => outward.min_by(|&m, &n| turn(straight, m).total_cmp(&turn(straight, n))),
+        Move::NorthWest => outward.min_by_key(|&n| cells[n].col),
+        Move::NorthEast => outward.max_by_key(|&n| cells[n].col),
+        Move::South => cell.inward,
+        Move::East => Some(cell.clockwise),
+        Move::West => Some(cell.counter_clockwise),
+        _ => None,
+    };
+    target.filter(|n| *n != ix && cell.links.contains(n))
+}
+
+/// Cells seen from `from`: those up to `SIGHT_RADIUS` steps away through passages, and
+/// every cell down a corridor that goes on straight from it. Grids decide what straight is
+/// through `GtkDrawable::player_move`.
+pub fn visible_cells(grid: &dyn GtkDrawable, from: usize) -> HashSet<usize> {
+    let mut visible = HashSet::from([from]);
+    let mut frontier = vec![from];
+    for _ in 0..SIGHT_RADIUS {
+        frontier = frontier
+            .into_iter()
+            .flat_map(|ix| MOVES.iter().filter_map(move |&m| grid.player_move(ix, m)))
+            .filter(|n| visible.insert(*n))
+            .collect();
+    }
+    for towards in MOVES {
+        // corridors around a ring or a cube come back to where they started
+        let mut corridor = HashSet::from([from]);
+        let mut ix = from;
+        while let Some(next) = grid.player_move(ix, towards) {
+            if !corridor.insert(next) {
+                break;
+            }
+            visible.insert(next);
+            ix = next;
+        }
+    }
+    visible
+}
+
+/// What the player knows of the maze in fog-of-war mode.
+pub struct Fog {
+    /// Every cell seen so far.
+    pub seen: HashSet<usize>,
+    /// Cells seen from where the player is now.
+    pub visible: HashSet<usize>,
+}
+
+impl Fog {
+    pub fn new(grid: &dyn GtkDrawable, from: usize) -> Fog {
+        let visible = visible_cells(grid, from);
+        Fog {
+            seen: visible.clone(),
+            visible,
+        }
+    }
+
+    pub fn look(&mut self, grid: &dyn GtkDrawable, from: usize) {
+        self.visible = visible_cells(grid, from);
+        self.seen.extend(&self.visible);
+    }
+
+    /// Covers everything but the visible cells, and dims the ones seen before.
+    pub fn draw(&self, grid: &dyn GtkDrawable, cr: &Context, cellsize: f64) {
+        cr.push_group();
+        cr.set_source_rgb(0.15, 0.15, 0.15);
+        cr.paint().expect("error while drawing fog");
+
+        cr.set_operator(Operator::DestOut);
+        cr.set_source_rgba(0., 0., 0., 0.5);
+        for &ix in self.seen.difference(&self.visible) {
+            grid.cell_path(cr, ix, cellsize);
+        }
+        cr.fill().expect("error while drawing fog");
+        cr.set_source_rgba(0., 0., 0., 1.);
+        for &ix in &self.visible {
+            grid.cell_path(cr, ix, cellsize);
+        }
+        cr.fill().expect("error while drawing fog");
+
+        cr.pop_group_to_source().expect("error while drawing fog");
+        cr.paint().expect("error while drawing fog");
+    }
+}
+
+/// A walk through the current maze, from the start to the goal of its longest path.
+pub struct Game {
+    pub position: usize,
+    pub goal: usize,
+    /// Cells the player has been on, in order, starting with the start cell.
+    pub trail: Vec<usize>,
+    pub moves: usize,
+    /// Set by the first move.
+    started: Option<Instant>,
+    /// Time taken, once the goal is reached.
+    pub finished: Option<Duration>,
+    /// Set in fog-of-war mode; lifted when the goal is reached.
+    pub fog: Option<Fog>,
+    /// Doors in the way and their keys, see `Puzzle`.
+    pub puzzle: Option<Puzzle>,
+    /// Keys picked up, one bit each.
+    pub keys: u32,
+}
+
+impl Game {
+    pub fn new(step: &DijkstraStep, puzzle: Option<Puzzle>) -> Game {
+        let goal = step.farthest();
+        let start = step.path_to(goal).first().copied().unwrap_or(goal);
+        let keys = puzzle.as_ref().map_or(0, |p| p.pick_up(start, 0));
+        Game {
+            position: start,
+            goal,
+            trail: vec![start],
+            moves: 0,
+            started: None,
+            finished: None,
+            fog: None,
+            puzzle,
+            keys,
+        }
+    }
+
+    pub fn is_won(&self) -> bool {
+        self.finished.is_some()
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        match (self.finished, self.started) {
+            (Some(time), _) => time,
+            (None, Some(started)) => started.elapsed(),
+            (None, None) => Duration::ZERO,
+        }
+    }
+
+    /// Moves the player if a passage leads that way and no locked door is in it. Returns
+    /// false if it does not, or the game is over.
+    pub fn try_move(&mut self, grid: &dyn GtkDrawable, towards: Move) -> bool {
+        if self.is_won() {
+            return false;
+        }
+        let Some(next) = grid.player_move(self.position, towards) else {
+            return false;
+        };
+        if let Some(puzzle) = &self.puzzle {
+            // doors and keys can be on the cell a tunnel passes through as well
+            let mut cells = vec![self.position];
+            cells.extend(grid.passed_through(self.position, towards));
+            cells.push(next);
+            let mut keys = self.keys;
+            for pair in cells.windows(2) {
+                if !puzzle.can_pass(pair[0], pair[1], keys) {
+                    return false;
+                }
+                keys = puzzle.pick_up(pair[1], keys);
+            }
+            self.keys = keys;
+        }
+        let started = *self.started.get_or_insert_with(Instant::now);
+        self.position = next;
+        self.trail.push(next);
+        self.moves += 1;
+        if let Some(fog) = &mut self.fog {
+            fog.look(grid, next);
+        }
+        if next == self.goal {
+            self.finished = Some(started.elapsed());
+        }
+        true
+    }
+
+    /// Turns fog-of-war on or off for the rest of the game.
+    pub fn set_fog(&mut self, grid: &dyn GtkDrawable, fog: bool) {
+        self.fog = fog.then(|| Fog::new(grid, self.position));
+    }
+
+    /// Whether the fog, if any, has let the player see the goal.
+    pub fn goal_seen(&self) -> bool {
+        self.is_won() || self.fog.as_ref().is_none_or(|fog| fog.seen.contains(&self.goal))
+    }
+
+    /// Draws the fog, the locked doors, the trail, the goal and the player in the grid's
+    /// drawing coordinates.
+    pub fn draw(&self, grid: &dyn GtkDrawable, cr: &Context, cellsize: f64) {
+        if let Some(fog) = &self.fog
+            && !self.is_won()
+        {
+            fog.draw(grid, cr, cellsize);
+        }
+        if let Some(puzzle) = &self.puzzle {
+            puzzle.draw(grid, cr, cellsize, self.keys);
+        }
+        cr.set_line_width(cellsize / 4.);
+        cr.set_source_rgba(0.2, 0.4, 1., 0.6);
+        for pair in self.trail.windows(2) {
+            let (x1, y1) = grid.cell_centre(pair[0], cellsize);
+            let (x2, y2) = grid.cell_centre(pair[1], cellsize);
+            if (x2 - x1).hypot(y2 - y1) <= MAX_TRAIL_STEP * cellsize {
+                cr.move_to(x1, y1);
+                cr.line_to(x2, y2);
+            }
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        // the goal stays hidden in the fog until the player has seen it
+        if self.goal_seen() {
+            let (x, y) = grid.cell_centre(self.goal, cellsize);
+            cr.set_source_rgb(0.9, 0.7, 0.);
+            cr.rectangle(
+                x - cellsize / 3.,
+                y - cellsize / 3.,
+                cellsize / 1.5,
+                cellsize / 1.5,
+            );
+            cr.fill().expect("error while drawing stroke");
+        }
+
+        let (x, y) = grid.cell_centre(self.position, cellsize);
+        cr.set_source_rgb(0., 0., 0.8);
+        cr.new_sub_path();
+        cr.arc(x, y, cellsize / 3., 0., 2. * PI);
+        cr.fill().expect("error while drawing stroke");
+    }
+
+    /// Banner over the middle of a widget of the given size once the goal is reached.
+    pub fn draw_win(&self, cr: &Context, width: f64, height: f64) {
+        if !self.is_won() {
+            return;
+        }
+        let text = format!(
+            "Solved in {} moves and {:.1} s",
+            self.moves,
+            self.elapsed().as_secs_f64()
+        );
+        cr.set_font_size(24.);
+        let extents = cr.text_extents(&text).expect("error while measuring text");
+        let (x, y) = (
+            (width - extents.width()) / 2.,
+            (height + extents.height()) / 2.,
+        );
+        cr.set_source_rgba(1., 1., 1., 0.85);
+        cr.rectangle(
+            x - 20.,
+            y - extents.height() - 20.,
+            extents.width() + 40.,
+            extents.height() + 40.,
+        );
+        cr.fill().expect("error while drawing stroke");
+        cr.set_source_rgb(0., 0.4, 0.);
+        cr.move_to(x - extents.x_bearing(), y);
+        cr.show_text(&text).expect("error while drawing text");
+    }
+}
@@ -1,8 +1,10 @@
-use crate::grid::{AbstractCell, AbstractGrid};
+use crate::export;
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition};
 use std::collections::HashSet;
 use std::fmt::{Display, Error, Formatter};
 
 use crate::draw_utils::GtkDrawable;
+use crate::play::{self, Move};
 use crate::solve::DijkstraStep;
 use gtk::cairo::Context;
 use gtk::prelude::*;
@@ -103,7 +105,11 @@ impl AbstractGrid<PolarCell> for CircularGrid {
             neighbours.push(ix)
         };
         neighbours.push(cell.counter_clockwise);
-        neighbours.push(cell.clockwise);
+        // rings of one or two cells are their own or each other's both ways round
+        if cell.clockwise != cell.counter_clockwise {
+            neighbours.push(cell.clockwise);
+        }
+        neighbours.retain(|&n| n != ix);
         neighbours
     }
 
@@ -120,22 +126,54 @@ impl AbstractGrid<PolarCell> for CircularGrid {
     }
 }
 
+/// How `CircularGrid` divides its rings into cells.
+#[derive(Clone, PartialEq, Debug)]
+pub struct PolarOptions {
+    /// Cells in the innermost ring, at least 2, or None to divide it like the others.
+    pub first_ring: Option<usize>,
+    /// Rings left out in the middle; 0 keeps a single centre cell.
+    pub hub: usize,
+    /// A ring's cells are split once they would be this many times wider than tall, into
+    /// cells about as wide as they are tall.
+    pub max_aspect: f64,
+}
+
+impl Default for PolarOptions {
+    fn default() -> PolarOptions {
+        PolarOptions {
+            first_ring: None,
+            hub: 0,
+            max_aspect: 1.5,
+        }
+    }
+}
+
 impl CircularGrid {
     pub fn new(rows: usize) -> CircularGrid {
+        CircularGrid::with_options(rows, &PolarOptions::default())
+    }
+
+    pub fn with_options(rows: usize, options: &PolarOptions) -> CircularGrid {
         let mut cells = Vec::new();
-        let mut cells_by_rows = Vec::new();
-        let row_height = 1.0 / (rows as f64);
+        let hub = options.hub.min(rows - 1);
+        let mut cells_by_rows = vec![Vec::new(); hub];
 
-        cells.push(PolarCell::new(0, 0, 0, 0, 1, Vec::new()));
-        cells_by_rows.push(vec![0]);
         let mut previous_count = 1;
-        for i in 1..rows {
-            let radius = (i as f64) / (rows as f64);
-            let circ = 2. * PI * radius;
-            let estimated_width = circ / (previous_count as f64);
-            let ratio = (estimated_width / row_height).round() as usize;
-
-            let cell_count = previous_count * ratio;
+        for i in hub..rows {
+            // ring widths are in ring heights
+            let estimated_width = 2. * PI * i as f64 / (previous_count as f64);
+            let ratio = if estimated_width < options.max_aspect {
+                1
+            } else {
+                (estimated_width.round() as usize).max(2)
+            };
+
+            let cell_count = match (i, options.first_ring) {
+                (0, _) => 1,
+                (_, Some(first)) if i == hub || (hub == 0 && i == 1) => first.max(2),
+                _ if i == hub => (2. * PI * i as f64).round().max(1.) as usize,
+                _ => previous_count * ratio,
+            };
             let mut cells_in_row = Vec::new();
             for j in 0..cell_count {
                 let current_cell_id = cells.len();
@@ -145,7 +183,7 @@ impl CircularGrid {
                     current_cell_id - 1
                 };
                 let cw = if j == cell_count - 1 {
-                    cells_in_row[0]
+                    current_cell_id + 1 - cell_count
                 } else {
                     current_cell_id + 1
                 };
@@ -156,8 +194,11 @@ impl CircularGrid {
             cells_by_rows.push(cells_in_row);
         }
 
-        for i in 1..cells.len() {
+        for i in 0..cells.len() {
             let row = cells[i].row;
+            if row == hub {
+                continue;
+            }
             let col = cells[i].col;
             let ratio = cells_by_rows[row].len() as f64 / cells_by_rows[row - 1].len() as f64;
             // TODO pay attention here
@@ -182,33 +223,51 @@ impl CircularGrid {
 
     #[allow(dead_code)]
     pub fn to_dot(&self) -> String {
-        let mut res = "graph g {".to_owned();
-
-        for i in 0..self.cells.len() {
-            let row = self.cells[i].row;
-            let col = self.cells[i].col;
-            let links = &self.cells[i].links;
-            // for ix in self.neighbours(i) {
-            for &ix in links {
-                let _col = self.cells[ix].col;
-                let _row = self.cells[ix].row;
-                // print only south and east neighbours to avoid duplicates
-
-                res.push_str(
-                    format!(
-                        "\"({},{})\" -> \"({},{})\" [color=blue] \n",
-                        row, col, _row, _col
-                    )
-                    .as_str(),
-                );
-            }
+        export::to_dot(self, false)
+    }
+}
+
+impl CellPosition for CircularGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        let cell = &self.cells[ix];
+        if cell.row == 0 {
+            return (0., 0.);
         }
-        res.push_str("\n}\n");
-        res
+        let theta = 2. * PI / (cell.columns as f64) * (cell.col as f64 + 0.5);
+        let r = cell.row as f64 + 0.5;
+        (r * theta.cos(), r * theta.sin())
     }
 }
 
 impl GtkDrawable for CircularGrid {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        let d = self.height as f64 * cellsize * 2.;
+        (d, d)
+    }
+
+    /// `position` is centred on the origin, the drawing on the middle of the canvas.
+    fn cell_centre(&self, ix: usize, cellsize: f64) -> (f64, f64) {
+        let (x, y) = self.position(ix);
+        let centre = self.height as f64 * cellsize;
+        (centre + x * cellsize, centre + y * cellsize)
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        play::polar_move(&self.cells, ix, towards)
+    }
+
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        let c = &self.cells[ix];
+        let centre = self.height as f64 * cellsize;
+        let theta = 2. * PI / c.columns as f64;
+        let (a0, a1) = (c.col as f64 * theta, (c.col + 1) as f64 * theta);
+        let (r0, r1) = (c.row as f64 * cellsize, (c.row + 1) as f64 * cellsize);
+        cr.new_sub_path();
+        cr.arc(centre, centre, r1, a0, a1);
+        cr.arc_negative(centre, centre, r0, a1, a0);
+        cr.close_path();
+    }
+
     fn draw_maze(&self, w: &DrawingArea, cr: &Context, actual_ring_height: f64) {
         let scalex = w.allocated_width() as f64 / (self.height as f64 * actual_ring_height * 2.);
         let scaley = w.allocated_height() as f64 / (self.height as f64 * actual_ring_height * 2.);
@@ -228,17 +287,18 @@ impl GtkDrawable for CircularGrid {
         );
         cr.stroke().expect("error while drawing stroke");
         for i in 0..self.len() {
-            if i == 0 {
+            let cell = self.cell(i);
+            if cell.row == 0 {
                 continue;
             }
-            let cell = self.cell(i);
-            let inward = self.inward_ix(i).unwrap();
             let theta = 2. * PI / (cell.columns as f64);
             let inner_r = ring_height * cell.row() as f64;
             let outer_r = ring_height * (cell.row() + 1) as f64;
             let theta_cw = theta * (cell.col() as f64);
             let theta_ccw = theta * ((cell.col() + 1) as f64);
-            if !cell.links().contains(&inward) {
+            // the innermost ring of a grid with a hub has no inward neighbours
+            let linked_inward = self.inward_ix(i).is_some_and(|ix| cell.links.contains(&ix));
+            if !linked_inward {
                 cr.arc(center_x, center_y, inner_r, theta_cw, theta_ccw);
                 cr.stroke().expect("error while drawing stroke");
             }
@@ -369,4 +429,17 @@ impl GtkDrawable for CircularGrid {
 
         cr.restore().expect("error while restoring coords");
     }
+
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let x = x * self.height as f64 * cellsize * 2. / w.allocated_width() as f64;
+        let y = y * self.height as f64 * cellsize * 2. / w.allocated_height() as f64;
+        let center = self.height as f64 * cellsize;
+        let (dx, dy) = (x - center, y - center);
+
+        let row = ((dx * dx + dy * dy).sqrt() / cellsize).floor() as usize;
+        let first = self.cells.iter().find(|c| c.row == row)?;
+        let theta = dy.atan2(dx).rem_euclid(2. * PI);
+        let col = (theta / (2. * PI / first.columns as f64)).floor() as usize;
+        Some((row, col.min(first.columns - 1)))
+    }
 }
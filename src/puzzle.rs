@@ -0,0 +1,266 @@
+use crate::draw_utils::GtkDrawable;
+use crate::grid::{AbstractCell, AbstractGrid};
+use crate::play::MAX_TRAIL_STEP;
+use crate::solve::{self, DijkstraStep};
+use gtk::cairo::Context;
+use rand::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::f64::consts::PI;
+
+/// Most doors a puzzle can have; held keys are kept as bits of a `u32`.
+pub const MAX_DOORS: usize = 8;
+
+/// Colours of the keys and the doors they open.
+const COLOURS: [(f64, f64, f64); MAX_DOORS] = [
+    (0.85, 0.1, 0.1),
+    (0.1, 0.55, 0.1),
+    (0.1, 0.3, 0.9),
+    (0.9, 0.5, 0.),
+    (0.6, 0.1, 0.7),
+    (0., 0.6, 0.6),
+    (0.9, 0.2, 0.6),
+    (0.45, 0.3, 0.1),
+];
+
+/// Locked doors on some links of a maze and the keys that open them. Door `k` opens for
+/// anyone holding key `k`, which lies in cell `keys[k]`; keys are picked up by walking onto
+/// their cell.
+#[derive(Clone, Debug)]
+pub struct Puzzle {
+    pub start: usize,
+    pub goal: usize,
+    /// Door numbers by link, with the smaller cell first.
+    doors: HashMap<(usize, usize), usize>,
+    pub keys: Vec<usize>,
+    /// Shortest walk from the start to the goal collecting the keys it needs, see
+    /// `solve::solve_with_keys`.
+    pub solution: Vec<usize>,
+}
+
+fn link(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+impl Puzzle {
+    /// Puts `doors` doors (at most `MAX_DOORS`) evenly along the solution of a solved maze,
+    /// and each door's key somewhere that can be reached through the doors before it only,
+    /// as far from the solution as possible. The number of doors sets the difficulty: every
+    /// one of them sends the player down a side branch and back.
+    pub fn place<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+        g: &T,
+        step: &DijkstraStep,
+        doors: usize,
+        rng: &mut ThreadRng,
+    ) -> Puzzle {
+        let goal = step.farthest();
+        let path = step.path_to(goal);
+        let start = path.first().copied().unwrap_or(goal);
+        let edges = path.len().saturating_sub(1);
+        let count = doors.min(MAX_DOORS).min(edges);
+        let mut puzzle = Puzzle {
+            start,
+            goal,
+            doors: HashMap::new(),
+            keys: Vec::new(),
+            solution: Vec::new(),
+        };
+        for k in 0..count {
+            let at = (k + 1) * edges / (count + 1);
+            puzzle.doors.insert(link(path[at], path[at + 1]), k);
+        }
+
+        let from_path = solve::distances(g, &path);
+        let mut reached = HashSet::new();
+        for k in 0..count {
+            // cells behind door k - 1 that door k still keeps closed
+            let region = puzzle.reachable(g, (1 << k) - 1);
+            let mut behind: Vec<usize> = region.difference(&reached).copied().collect();
+            // loops in braided mazes can lead around the door before
+            if behind.is_empty() {
+                behind = region.iter().copied().collect();
+            }
+            let deepest = behind.iter().map(|&ix| from_path[ix]).max().flatten();
+            behind.retain(|&ix| from_path[ix] == deepest);
+            behind.sort_unstable();
+            puzzle.keys.push(behind[rng.random_range(0..behind.len())]);
+            reached = region;
+        }
+
+        puzzle.solution =
+            solve::solve_with_keys(g, &puzzle).expect("every key lies before its door");
+        puzzle
+    }
+
+    /// Number of the door on the link between `a` and `b`, if any.
+    pub fn door(&self, a: usize, b: usize) -> Option<usize> {
+        self.doors.get(&link(a, b)).copied()
+    }
+
+    /// Whether the link between `a` and `b` can be passed holding `keys`.
+    pub fn can_pass(&self, a: usize, b: usize, keys: u32) -> bool {
+        self.door(a, b).is_none_or(|k| keys & (1 << k) != 0)
+    }
+
+    /// Keys held after stepping onto `ix` holding `keys`.
+    pub fn pick_up(&self, ix: usize, keys: u32) -> u32 {
+        self.keys
+            .iter()
+            .enumerate()
+            .filter(|&(_, &cell)| cell == ix)
+            .fold(keys, |keys, (k, _)| keys | 1 << k)
+    }
+
+    /// Cells reachable from the start holding `keys`, without picking up more.
+    fn reachable<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+        &self,
+        g: &T,
+        keys: u32,
+    ) -> HashSet<usize> {
+        let mut seen = HashSet::from([self.start]);
+        let mut queue = VecDeque::from([self.start]);
+        while let Some(ix) = queue.pop_front() {
+            for n in g.links(ix) {
+                if self.can_pass(ix, n, keys) && seen.insert(n) {
+                    queue.push_back(n);
+                }
+            }
+        }
+        seen
+    }
+
+    /// Draws the doors still locked for `keys` across their links and the keys not held
+    /// yet, in the grid's unstretched drawing coordinates.
+    pub fn draw(&self, grid: &dyn GtkDrawable, cr: &Context, cellsize: f64, keys: u32) {
+        let held = |k: usize| keys & (1 << k) != 0;
+        cr.set_line_width(cellsize / 4.);
+        for (&(a, b), &k) in &self.doors {
+            if held(k) {
+                continue;
+            }
+            let (x1, y1) = grid.cell_centre(a, cellsize);
+            let (x2, y2) = grid.cell_centre(b, cellsize);
+            let length = (x2 - x1).hypot(y2 - y1);
+            // links between parts of the drawing have no wall to put the door in
+            if length == 0. || length > MAX_TRAIL_STEP * cellsize {
+                continue;
+            }
+            let (mx, my) = ((x1 + x2) / 2., (y1 + y2) / 2.);
+            let (dx, dy) = (
+                (y1 - y2) / length * cellsize * 0.45,
+                (x2 - x1) / length * cellsize * 0.45,
+            );
+            let (r, g, b) = COLOURS[k];
+            cr.set_source_rgb(r, g, b);
+            cr.move_to(mx - dx, my - dy);
+            cr.line_to(mx + dx, my + dy);
+            cr.stroke().expect("error while drawing stroke");
+        }
+
+        cr.set_line_width(cellsize / 10.);
+        for (k, &ix) in self.keys.iter().enumerate() {
+            if held(k) {
+                continue;
+            }
+            let (x, y) = grid.cell_centre(ix, cellsize);
+            let (r, g, b) = COLOURS[k];
+            cr.new_sub_path();
+            cr.arc(x, y, cellsize / 4., 0., 2. * PI);
+            cr.set_source_rgb(r, g, b);
+            cr.fill_preserve().expect("error while drawing stroke");
+            cr.set_source_rgb(0., 0., 0.);
+            cr.stroke().expect("error while drawing stroke");
+        }
+    }
+
+    /// Draws `solution` as a dashed line.
+    pub fn draw_solution(&self, grid: &dyn GtkDrawable, cr: &Context, cellsize: f64) {
+        cr.save().expect("error while saving coords");
+        cr.set_source_rgb(0.3, 0.3, 0.3);
+        cr.set_line_width(cellsize / 8.);
+        cr.set_dash(&[cellsize / 3., cellsize / 4.], 0.);
+        for pair in self.solution.windows(2) {
+            let (x1, y1) = grid.cell_centre(pair[0], cellsize);
+            let (x2, y2) = grid.cell_centre(pair[1], cellsize);
+            if (x2 - x1).hypot(y2 - y1) <= MAX_TRAIL_STEP * cellsize {
+                cr.move_to(x1, y1);
+                cr.line_to(x2, y2);
+            }
+        }
+        cr.stroke().expect("error while drawing stroke");
+        cr.restore().expect("error while restoring coords");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_utils::{make_tha_maze, GenerationType};
+    use crate::rectangle::RegularGrid;
+
+    /// A 2x3 grid with a corridor along the top row and a side branch down from its middle:
+    ///
+    /// 0 - 1 - 2
+    ///     |
+    /// 3   4   5
+    fn branch() -> RegularGrid {
+        let mut g = RegularGrid::new(2, 3);
+        g.link(0, 1);
+        g.link(1, 2);
+        g.link(1, 4);
+        g
+    }
+
+    fn locked(keys: Vec<usize>) -> Puzzle {
+        Puzzle {
+            start: 0,
+            goal: 2,
+            doors: HashMap::from([(link(1, 2), 0)]),
+            keys,
+            solution: Vec::new(),
+        }
+    }
+
+    /// Checks that `walk` goes from the start to the goal through passages, holding the key
+    /// of every door it passes.
+    fn assert_walkable<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+        g: &T,
+        puzzle: &Puzzle,
+        walk: &[usize],
+    ) {
+        assert_eq!(walk.first(), Some(&puzzle.start));
+        assert_eq!(walk.last(), Some(&puzzle.goal));
+        let mut keys = puzzle.pick_up(puzzle.start, 0);
+        for pair in walk.windows(2) {
+            assert!(g.links(pair[0]).contains(&pair[1]), "no passage {:?}", pair);
+            assert!(puzzle.can_pass(pair[0], pair[1], keys), "locked {:?}", pair);
+            keys = puzzle.pick_up(pair[1], keys);
+        }
+    }
+
+    #[test]
+    fn solves_with_key_behind_door() {
+        let g = branch();
+        let puzzle = locked(vec![4]);
+        let walk = solve::solve_with_keys(&g, &puzzle).unwrap();
+        assert_eq!(walk, vec![0, 1, 4, 1, 2]);
+        assert_walkable(&g, &puzzle, &walk);
+    }
+
+    #[test]
+    fn no_solution_with_key_beyond_door() {
+        let g = branch();
+        assert_eq!(solve::solve_with_keys(&g, &locked(vec![2])), None);
+    }
+
+    #[test]
+    fn places_doors_on_braided_mazes() {
+        for _ in 0..20 {
+            let mut g = RegularGrid::new(12, 12);
+            make_tha_maze(&GenerationType::RecursiveBacktracker, &mut g, 255);
+            let step = solve::solve_with_longest_path(&g);
+            let puzzle = Puzzle::place(&g, &step, MAX_DOORS, &mut rand::rng());
+            assert_eq!(puzzle.keys.len(), puzzle.doors.len());
+            assert_walkable(&g, &puzzle, &puzzle.solution);
+        }
+    }
+}
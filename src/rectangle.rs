@@ -1,5 +1,10 @@
-use crate::draw_utils::GtkDrawable;
-use crate::grid::{AbstractCell, AbstractGrid, CompassDirections, RectangularGrid};
+use crate::draw_utils::{self, GtkDrawable};
+use crate::export;
+use crate::grid::{
+    AbstractCell, AbstractGrid, CellPosition, CompassDirections, CompassGrid, RectangularGrid,
+};
+use crate::mask::Mask;
+use crate::play::{self, Move};
 use crate::solve::DijkstraStep;
 use gtk::cairo::Context;
 use gtk::prelude::*;
@@ -43,11 +48,25 @@ impl Cell {
     }
 }
 
+/// Which borders of a rectangular grid join up with the opposite side.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Wrap {
+    None,
+    /// East and west edges meet.
+    Cylinder,
+    /// East meets west and north meets south.
+    Torus,
+    /// East and west edges meet upside down: row r on one side joins row height - 1 - r.
+    Moebius,
+}
+
 #[derive(Clone)]
 pub struct RegularGrid {
     pub width: usize,
     pub height: usize,
     pub cells: Vec<Cell>,
+    pub mask: Mask,
+    pub wrap: Wrap,
 }
 
 impl RectangularGrid for RegularGrid {
@@ -63,41 +82,97 @@ impl CompassDirections for RegularGrid {
     fn north_ix(&self, ix: usize) -> Option<usize> {
         let row = self.cells[ix].row;
         let col = self.cells[ix].col;
-        self.ix_opt(row.wrapping_sub(1), col)
+        self.wrapped_ix(row as isize - 1, col as isize)
     }
 
     fn east_ix(&self, ix: usize) -> Option<usize> {
         let row = self.cells[ix].row;
         let col = self.cells[ix].col;
-        self.ix_opt(row, col.wrapping_add(1))
+        self.wrapped_ix(row as isize, col as isize + 1)
     }
 
     fn west_ix(&self, ix: usize) -> Option<usize> {
         let row = self.cells[ix].row;
         let col = self.cells[ix].col;
-        self.ix_opt(row, col.wrapping_sub(1))
+        self.wrapped_ix(row as isize, col as isize - 1)
     }
 
     fn south_ix(&self, ix: usize) -> Option<usize> {
         let row = self.cells[ix].row;
         let col = self.cells[ix].col;
-        self.ix_opt(row.wrapping_add(1), col)
+        self.wrapped_ix(row as isize + 1, col as isize)
     }
 }
 
 impl RegularGrid {
     pub fn new(row: usize, col: usize) -> RegularGrid {
+        RegularGrid::masked(Mask::new(row, col))
+    }
+
+    pub fn masked(mask: Mask) -> RegularGrid {
         let mut gridarr = Vec::new();
-        for i in 0..row {
-            for j in 0..col {
+        for i in 0..mask.rows {
+            for j in 0..mask.cols {
                 gridarr.push(Cell::new(i, j));
             }
         }
         RegularGrid {
-            width: col,
-            height: row,
+            width: mask.cols,
+            height: mask.rows,
             cells: gridarr,
+            mask,
+            wrap: Wrap::None,
+        }
+    }
+
+    /// Index of (row, col), following the wrap setting when it is outside the grid.
+    fn wrapped_ix(&self, row: isize, col: isize) -> Option<usize> {
+        let (h, w) = (self.height as isize, self.width as isize);
+        let (mut row, mut col) = (row, col);
+        if !(0..w).contains(&col) {
+            match self.wrap {
+                Wrap::None => return None,
+                Wrap::Cylinder | Wrap::Torus => {}
+                Wrap::Moebius => row = h - 1 - row,
+            }
+            col = col.rem_euclid(w);
+        }
+        if !(0..h).contains(&row) {
+            if self.wrap != Wrap::Torus {
+                return None;
+            }
+            row = row.rem_euclid(h);
+        }
+        Some(self.ix(row as usize, col as usize))
+    }
+
+    /// Dashed lines along the borders that wrap around.
+    fn draw_seams(&self, cr: &Context, cellsize: f64) {
+        if self.wrap == Wrap::None {
+            return;
+        }
+        let (w, h) = (self.width as f64 * cellsize, self.height as f64 * cellsize);
+        cr.save().expect("error while saving coords");
+        cr.set_source_rgb(0.2, 0.4, 1.);
+        cr.set_dash(&[cellsize / 4.], 0.);
+        cr.move_to(0., 0.);
+        cr.line_to(0., h);
+        cr.move_to(w, 0.);
+        cr.line_to(w, h);
+        if self.wrap == Wrap::Torus {
+            cr.move_to(0., 0.);
+            cr.line_to(w, 0.);
+            cr.move_to(0., h);
+            cr.line_to(w, h);
         }
+        cr.stroke().expect("error while drawing stroke");
+        cr.restore().expect("error while restoring coords");
+    }
+
+    /// Whether two cells sit side by side in the drawing, rather than across a seam.
+    pub fn adjacent(&self, ix1: usize, ix2: usize) -> bool {
+        let (c1, c2) = (&self.cells[ix1], &self.cells[ix2]);
+        c1.row.abs_diff(c2.row) + c1.col.abs_diff(c2.col) == 1
     }
 
     #[allow(dead_code)]
@@ -145,35 +220,38 @@ impl RegularGrid {
 
     #[allow(dead_code)]
     pub fn to_dot(&self) -> String {
-        let mut res = "graph g {".to_owned();
-
-        for c in &self.cells {
-            for &ix in c.links.iter() {
-                let col = self.cells[ix].col;
-                let row = self.cells[ix].row;
-                // print only south and east neighbours to avoid duplicates
-                if row >= c.row && col >= c.col {
-                    res.push_str(
-                        format!("\"({},{})\" -- \"({},{})\" \n", c.row, c.col, col, row).as_str(),
-                    );
-                }
-            }
-        }
+        export::to_dot(self, false)
+    }
+}
 
-        res.push_str("\n}\n");
-        res
+impl CellPosition for RegularGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        let c = &self.cells[ix];
+        (c.col as f64 + 0.5, c.row as f64 + 0.5)
     }
 }
 
 impl Display for RegularGrid {
     fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        // borders that wrap are open where a cell links across them
+        let open = |ix: usize, other: Option<usize>| {
+            other.is_some_and(|o| self.cells[ix].links.contains(&o))
+        };
         write!(f, "+")?;
-        for _ in 0..self.width {
-            write!(f, "---+")?;
+        for j in 0..self.width {
+            let ix = self.ix(0, j);
+            let top = if open(ix, self.north_ix(ix)) {
+                "   "
+            } else {
+                "---"
+            };
+            write!(f, "{}+", top)?;
         }
         writeln!(f)?;
         for i in 0..self.height {
-            let mut top = "|".to_owned();
+            let ix = self.ix(i, 0);
+            let left = if open(ix, self.west_ix(ix)) { " " } else { "|" };
+            let mut top = left.to_owned();
             let mut bottom = "+".to_owned();
             for j in 0..self.width {
                 let body = "   ";
@@ -199,8 +277,13 @@ impl Display for RegularGrid {
     }
 }
 
+impl CompassGrid<Cell> for RegularGrid {}
+
 impl AbstractGrid<Cell> for RegularGrid {
     fn neighbours(&self, ix: usize) -> Vec<usize> {
+        if !self.is_active(ix) {
+            return Vec::new();
+        }
         let neighbors = [
             self.north_ix(ix),
             self.east_ix(ix),
@@ -208,10 +291,21 @@ impl AbstractGrid<Cell> for RegularGrid {
             self.south_ix(ix),
         ];
 
-        let neighbors: Vec<usize> = neighbors.iter().filter_map(|x| *x).collect();
+        let mut neighbors: Vec<usize> = neighbors
+            .iter()
+            .filter_map(|x| *x)
+            .filter(|&x| x != ix && self.is_active(x))
+            .collect();
+        // narrow wrapped grids reach the same cell both ways round
+        neighbors.sort();
+        neighbors.dedup();
         neighbors
     }
 
+    fn is_active(&self, ix: usize) -> bool {
+        self.mask.is_on(self.cells[ix].row, self.cells[ix].col)
+    }
+
     fn len(&self) -> usize {
         self.cells.len()
     }
@@ -223,9 +317,31 @@ impl AbstractGrid<Cell> for RegularGrid {
     fn cell_mut(&mut self, ix: usize) -> &mut Cell {
         &mut self.cells[ix]
     }
+
+    fn as_compass(&mut self) -> Option<&mut dyn CompassGrid<Cell>> {
+        Some(self)
+    }
 }
 
 impl GtkDrawable for RegularGrid {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        (self.width as f64 * cellsize, self.height as f64 * cellsize)
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        play::compass_move(self, ix, towards)
+    }
+
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        let c = &self.cells[ix];
+        cr.rectangle(
+            c.col as f64 * cellsize,
+            c.row as f64 * cellsize,
+            cellsize,
+            cellsize,
+        );
+    }
+
     fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
         let scalex = w.allocated_width() as f64 / (self.width as f64 * cellsize);
         let scaley = w.allocated_height() as f64 / (self.height as f64 * cellsize);
@@ -233,6 +349,9 @@ impl GtkDrawable for RegularGrid {
         cr.scale(scalex, scaley);
         cr.set_line_width(1.0);
         for ix in 0..self.len() {
+            if !self.is_active(ix) {
+                continue;
+            }
             let cur_cell = self.cell(ix);
             let draw_line = |item: &Option<usize>, end: (f64, f64)| match item {
                 Some(r_idx) if !cur_cell.links().contains(r_idx) => cr.line_to(end.0, end.1),
@@ -250,6 +369,36 @@ impl GtkDrawable for RegularGrid {
             draw_line(&self.north_ix(ix), (x1, y1));
             cr.stroke().expect("error while drawing stroke");
         }
+        self.draw_seams(cr, cellsize);
+    }
+
+    fn draw_maze_inset(&self, w: &DrawingArea, cr: &Context, cellsize: f64, inset: f64) {
+        cr.save().expect("error while saving coords");
+        let scalex = w.allocated_width() as f64 / (self.width as f64 * cellsize);
+        let scaley = w.allocated_height() as f64 / (self.height as f64 * cellsize);
+        cr.scale(scalex, scaley);
+        cr.set_line_width(1.0);
+        for ix in (0..self.len()).filter(|&ix| self.is_active(ix)) {
+            let c = &self.cells[ix];
+            let (x, y) = (c.col as f64 * cellsize, c.row as f64 * cellsize);
+            let open = [
+                self.north_ix(ix),
+                self.east_ix(ix),
+                self.south_ix(ix),
+                self.west_ix(ix),
+            ]
+            .map(|n| n.is_some_and(|n| c.links.contains(&n)));
+            let corners = [
+                (x, y),
+                (x + cellsize, y),
+                (x + cellsize, y + cellsize),
+                (x, y + cellsize),
+            ];
+            let centre = (x + cellsize / 2., y + cellsize / 2.);
+            draw_utils::draw_inset_polygon(cr, &corners, centre, &open, inset);
+        }
+        self.draw_seams(cr, cellsize);
+        cr.restore().expect("error while restoring coords");
     }
 
     fn draw_pathfind(
@@ -288,26 +437,18 @@ impl GtkDrawable for RegularGrid {
             cr.fill().expect("error while drawing stroke");
         };
 
-        let mut max_idx = 0;
-        let mut min_idx = 0;
-        let mut max_length = step_state.cell_weights[max_idx].path_length;
-        let mut min_length = max_length;
-        for (i, c) in step_state.cell_weights.iter().enumerate() {
-            if c.path_length > max_length {
-                max_length = c.path_length;
-                max_idx = i;
-            }
-            if c.path_length < min_length {
-                min_length = c.path_length;
-                min_idx = i;
-            }
-        }
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let min_idx = step_state.path_to(max_idx)[0];
 
         let cur_cell = self.cell(min_idx);
         let end_cell = self.cell(max_idx);
 
         cr.set_line_width(6.0);
         for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 {
+                continue;
+            }
             let intensity = (max_length - c.path_length) as f64 / max_length as f64;
             let dark = intensity;
             let bright = 0.5 + intensity / 2.;
@@ -336,12 +477,30 @@ impl GtkDrawable for RegularGrid {
             let mut seen = HashSet::new();
             while cur_cell != (min_idx as i32) && !seen.contains(&cur_cell) {
                 seen.insert(cur_cell);
-                let (x2, y2) = coords(step_state.cell_weights[cur_cell as usize].parent);
-                cr.line_to(x2, y2);
-                cur_cell = step_state.cell_weights[cur_cell as usize].parent;
+                let parent = step_state.cell_weights[cur_cell as usize].parent;
+                let (x2, y2) = coords(parent);
+                // the path leaves through one seam and comes back through the other
+                if self.adjacent(cur_cell as usize, parent as usize) {
+                    cr.line_to(x2, y2);
+                } else {
+                    cr.move_to(x2, y2);
+                }
+                cur_cell = parent;
             }
             cr.stroke().expect("error while drawing stroke");
         }
         cr.restore().expect("error while restoring coords");
     }
+
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let scalex = w.allocated_width() as f64 / (self.width as f64 * cellsize);
+        let scaley = w.allocated_height() as f64 / (self.height as f64 * cellsize);
+        let col = (x / scalex / cellsize).floor();
+        let row = (y / scaley / cellsize).floor();
+        if col < 0. || row < 0. {
+            return None;
+        }
+        let ix = self.ix_opt(row as usize, col as usize)?;
+        Some((self.cells[ix].row, self.cells[ix].col))
+    }
 }
@@ -1,5 +1,7 @@
 use crate::grid::{AbstractCell, AbstractGrid};
-use std::collections::VecDeque;
+use crate::puzzle::Puzzle;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, VecDeque};
 use std::fmt::{Display, Error, Formatter};
 
 #[derive(Clone, Debug)]
@@ -80,12 +82,40 @@ impl DijkstraStep {
             cell_weights,
         }
     }
+
+    /// Index of the reached cell furthest from the start.
+    pub fn farthest(&self) -> usize {
+        let mut max_idx = 0;
+        for (i, c) in self.cell_weights.iter().enumerate() {
+            if c.path_length > self.cell_weights[max_idx].path_length {
+                max_idx = i;
+            }
+        }
+        max_idx
+    }
+
+    /// Cells on the path from the start to `target`, start first. Empty if `target` was
+    /// not reached.
+    pub fn path_to(&self, target: usize) -> Vec<usize> {
+        let mut path = Vec::new();
+        if self.cell_weights[target].parent < 0 {
+            return path;
+        }
+        let mut cur = target;
+        path.push(cur);
+        while self.cell_weights[cur].parent as usize != cur {
+            cur = self.cell_weights[cur].parent as usize;
+            path.push(cur);
+        }
+        path.reverse();
+        path
+    }
 }
 
 pub fn solve_with_longest_path<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
     g: &T,
 ) -> DijkstraStep {
-    let start = 0;
+    let start = (0..g.len()).find(|&ix| g.is_active(ix)).unwrap_or(0);
     // solve initially from random point
     let mut result = DijkstraStep::initial(g, start);
     while !result.lookup_queue.is_empty() {
@@ -109,3 +139,64 @@ pub fn solve_with_longest_path<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?S
     }
     result
 }
+
+/// Steps through passages from the nearest of `sources` to every cell, None where none of
+/// them is reached.
+pub fn distances<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+    sources: &[usize],
+) -> Vec<Option<usize>> {
+    let mut distance = vec![None; g.len()];
+    let mut queue = VecDeque::new();
+    for &ix in sources {
+        distance[ix] = Some(0);
+        queue.push_back(ix);
+    }
+    while let Some(ix) = queue.pop_front() {
+        let d = distance[ix].unwrap_or(0);
+        for n in g.links(ix) {
+            if distance[n].is_none() {
+                distance[n] = Some(d + 1);
+                queue.push_back(n);
+            }
+        }
+    }
+    distance
+}
+
+/// Shortest walk from the start of a keys-and-doors puzzle to its goal, searching over
+/// states of a cell and the keys held there, so the walk can go back the way it came once
+/// it has a key. Doors only open with their key; keys are picked up on entering their cell.
+/// None if the goal cannot be reached.
+pub fn solve_with_keys<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+    puzzle: &Puzzle,
+) -> Option<Vec<usize>> {
+    let start = (puzzle.start, puzzle.pick_up(puzzle.start, 0));
+    let mut parents = HashMap::from([(start, start)]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(state) = queue.pop_front() {
+        let (cell, keys) = state;
+        if cell == puzzle.goal {
+            let mut path = vec![cell];
+            let mut cur = state;
+            while parents[&cur] != cur {
+                cur = parents[&cur];
+                path.push(cur.0);
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for n in g.links(cell) {
+            if !puzzle.can_pass(cell, n, keys) {
+                continue;
+            }
+            let next = (n, puzzle.pick_up(n, keys));
+            if let Entry::Vacant(e) = parents.entry(next) {
+                e.insert(state);
+                queue.push_back(next);
+            }
+        }
+    }
+    None
+}
@@ -0,0 +1,458 @@
+use crate::draw_utils::{orthographic, polygon_path, GtkDrawable};
+use crate::grid::{AbstractGrid, CellPosition};
+use crate::polar::PolarCell;
+use crate::play::{self, Move};
+use crate::solve::DijkstraStep;
+use gtk::cairo::Context;
+use gtk::prelude::*;
+use gtk::DrawingArea;
+use std::f64::consts::PI;
+
+/// A wall on the sphere: part of a circle of latitude at a colatitude, between two
+/// longitudes, or part of a meridian at a longitude, between two colatitudes.
+enum Wall {
+    Latitude(f64, f64, f64),
+    Meridian(f64, f64, f64),
+}
+
+/// Latitude rings like `CircularGrid`'s, from the north pole down to the equator and
+/// mirrored for the southern hemisphere. `height` is the number of rings per hemisphere;
+/// rows count from the north pole to the south pole, `inward` points to a cell's own pole
+/// and the two equator rings are each other's `outward`.
+#[derive(Clone)]
+pub struct SphereGrid {
+    pub height: usize,
+    pub cells: Vec<PolarCell>,
+}
+
+impl SphereGrid {
+    pub fn new(rows: usize) -> SphereGrid {
+        let ring_height = Self::ring_height(rows);
+        let mut counts = vec![1];
+        for i in 1..rows {
+            let previous_count = counts[i - 1];
+            let circ = 2. * PI * (i as f64 * ring_height).sin();
+            let estimated_width = circ / (previous_count as f64);
+            let ratio = (estimated_width / ring_height).round() as usize;
+            counts.push(previous_count * ratio.max(1));
+        }
+        let mirrored: Vec<usize> = counts.iter().rev().cloned().collect();
+        counts.extend(mirrored);
+
+        let mut cells = Vec::new();
+        let mut first_in_row = Vec::new();
+        for (row, &cell_count) in counts.iter().enumerate() {
+            let first = cells.len();
+            for col in 0..cell_count {
+                let cw = first + (col + 1) % cell_count;
+                let ccw = first + (col + cell_count - 1) % cell_count;
+                cells.push(PolarCell::new(row, col, cw, ccw, cell_count, Vec::new()));
+            }
+            first_in_row.push(first);
+        }
+
+        for i in 0..cells.len() {
+            let row = cells[i].row;
+            let pole_row = match row {
+                0 => continue,
+                _ if row < rows => row - 1,
+                _ if row + 1 < 2 * rows => row + 1,
+                _ => continue,
+            };
+            let parent = first_in_row[pole_row] + cells[i].col * counts[pole_row] / counts[row];
+            cells[parent].outward.push(i);
+            cells[i].inward = Some(parent);
+        }
+
+        let (north, south) = (first_in_row[rows - 1], first_in_row[rows]);
+        for col in 0..counts[rows] {
+            cells[north + col].outward.push(south + col);
+            cells[south + col].outward.push(north + col);
+        }
+
+        SphereGrid {
+            height: rows,
+            cells,
+        }
+    }
+
+    /// Angle between the circles of latitude bounding a ring.
+    fn ring_height(rows: usize) -> f64 {
+        PI / (2 * rows) as f64
+    }
+
+    fn is_north(&self, ix: usize) -> bool {
+        self.cells[ix].row < self.height
+    }
+
+    /// Colatitudes and longitudes bounding a cell.
+    fn bounds(&self, ix: usize) -> (f64, f64, f64, f64) {
+        let cell = &self.cells[ix];
+        let h = Self::ring_height(self.height);
+        let theta = 2. * PI / cell.columns as f64;
+        (
+            cell.row as f64 * h,
+            (cell.row + 1) as f64 * h,
+            cell.col as f64 * theta,
+            (cell.col + 1) as f64 * theta,
+        )
+    }
+
+    /// Colatitude and longitude of the middle of a cell; for the pole caps, the pole.
+    fn centre(&self, ix: usize) -> (f64, f64) {
+        let (t0, t1, p0, p1) = self.bounds(ix);
+        let t = match self.cells[ix].row {
+            0 => 0.,
+            row if row == 2 * self.height - 1 => PI,
+            _ => (t0 + t1) / 2.,
+        };
+        (t, (p0 + p1) / 2.)
+    }
+
+    fn walls(&self) -> Vec<Wall> {
+        let mut walls = Vec::new();
+        for ix in 0..self.len() {
+            let cell = &self.cells[ix];
+            let (t0, t1, p0, p1) = self.bounds(ix);
+            if let Some(inward) = cell.inward
+                && !cell.links.contains(&inward)
+            {
+                let t = if self.is_north(ix) { t0 } else { t1 };
+                walls.push(Wall::Latitude(t, p0, p1));
+            }
+            if cell.clockwise != ix && !cell.links.contains(&cell.clockwise) {
+                walls.push(Wall::Meridian(p1, t0, t1));
+            }
+            if cell.row == self.height - 1 && !cell.links.contains(&(ix + cell.columns)) {
+                walls.push(Wall::Latitude(t1, p0, p1));
+            }
+        }
+        walls
+    }
+
+    /// Centres of the northern disc, the southern disc and the projection.
+    fn centres(&self, cellsize: f64) -> [(f64, f64); 3] {
+        let r = self.height as f64 * cellsize;
+        [
+            (r, r),
+            (3. * r + cellsize, r),
+            (5. * r + 2. * cellsize, r),
+        ]
+    }
+
+    /// Point (colatitude, longitude) in the net: each hemisphere is a disc seen from above
+    /// its pole, so the southern one is mirrored. Returns the disc centre, radius and angle.
+    fn to_net(&self, t: f64, p: f64, north: bool, cellsize: f64) -> ((f64, f64), f64, f64) {
+        let h = Self::ring_height(self.height);
+        let [n, s, _] = self.centres(cellsize);
+        match north {
+            true => (n, t / h * cellsize, p),
+            false => (s, (PI - t) / h * cellsize, -p),
+        }
+    }
+
+    fn to_sphere(&self, t: f64, p: f64) -> [f64; 3] {
+        [t.sin() * p.cos(), t.cos(), t.sin() * p.sin()]
+    }
+
+    /// Screen position and depth of a point on the sphere in the projection.
+    fn project(&self, v: [f64; 3], cellsize: f64) -> (f64, f64, f64) {
+        let [x, y, z] = orthographic(v);
+        let (cx, cy) = self.centres(cellsize)[2];
+        let r = self.height as f64 * cellsize;
+        (cx + x * r, cy + y * r, z)
+    }
+
+    /// Points along a wall, close enough together to draw it with straight lines.
+    fn samples(&self, wall: &Wall) -> Vec<[f64; 3]> {
+        let (from, to) = match wall {
+            Wall::Latitude(_, p0, p1) => (*p0, *p1),
+            Wall::Meridian(_, t0, t1) => (*t0, *t1),
+        };
+        let steps = ((to - from) / (PI / 36.)).ceil().max(1.) as usize;
+        (0..=steps)
+            .map(|i| {
+                let a = from + (to - from) * i as f64 / steps as f64;
+                match wall {
+                    Wall::Latitude(t, _, _) => self.to_sphere(*t, a),
+                    Wall::Meridian(p, _, _) => self.to_sphere(a, *p),
+                }
+            })
+            .collect()
+    }
+
+    /// Draws the visible parts of a line on the sphere through `points`.
+    fn draw_projected(&self, cr: &Context, points: &[[f64; 3]], cellsize: f64) {
+        for pair in points.windows(2) {
+            let (x1, y1, z1) = self.project(pair[0], cellsize);
+            let (x2, y2, z2) = self.project(pair[1], cellsize);
+            if z1 >= 0. && z2 >= 0. {
+                cr.move_to(x1, y1);
+                cr.line_to(x2, y2);
+            }
+        }
+    }
+}
+
+impl CellPosition for SphereGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        let (t, p) = self.centre(ix);
+        let ((cx, cy), r, a) = self.to_net(t, p, self.is_north(ix), 1.);
+        (cx + r * a.cos(), cy + r * a.sin())
+    }
+}
+
+impl AbstractGrid<PolarCell> for SphereGrid {
+    fn neighbours(&self, ix: usize) -> Vec<usize> {
+        let cell = &self.cells[ix];
+        let mut neighbours = cell.outward.clone();
+        if let Some(ix) = cell.inward {
+            neighbours.push(ix)
+        };
+        neighbours.push(cell.counter_clockwise);
+        neighbours.push(cell.clockwise);
+        // rings of one or two cells are their own or each other's both ways round
+        neighbours.retain(|&n| n != ix);
+        neighbours.sort();
+        neighbours.dedup();
+        neighbours
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn cell(&self, ix: usize) -> &PolarCell {
+        &self.cells[ix]
+    }
+
+    fn cell_mut(&mut self, ix: usize) -> &mut PolarCell {
+        &mut self.cells[ix]
+    }
+}
+
+impl GtkDrawable for SphereGrid {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        let d = 2. * self.height as f64 * cellsize;
+        (3. * d + 2. * cellsize, d)
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        // the southern disc of the net is mirrored top to bottom, which turns clockwise
+        // around its rings into counter clockwise, and the moves out of its pole cap
+        let south_pole = self.cells[ix].row == 2 * self.height - 1;
+        let towards = match towards {
+            _ if self.is_north(ix) => towards,
+            Move::North if south_pole => Move::South,
+            Move::South if south_pole => Move::North,
+            Move::NorthEast if south_pole => Move::SouthEast,
+            Move::SouthEast if south_pole => Move::NorthEast,
+            Move::NorthWest if south_pole => Move::SouthWest,
+            Move::SouthWest if south_pole => Move::NorthWest,
+            _ if south_pole => towards,
+            Move::East => Move::West,
+            Move::West => Move::East,
+            Move::NorthEast => Move::NorthWest,
+            Move::NorthWest => Move::NorthEast,
+            other => other,
+        };
+        play::polar_move(&self.cells, ix, towards)
+    }
+
+    /// The ring sector in the net, and the outline in the projection when the cell faces
+    /// the viewer.
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        let (t0, t1, p0, p1) = self.bounds(ix);
+        let north = self.is_north(ix);
+        let ((cx, cy), r0, _) = self.to_net(t0, 0., north, cellsize);
+        let (_, r1, _) = self.to_net(t1, 0., north, cellsize);
+        let (a0, a1) = if north { (p0, p1) } else { (-p1, -p0) };
+        cr.new_sub_path();
+        cr.arc(cx, cy, r0.max(r1), a0, a1);
+        cr.arc_negative(cx, cy, r0.min(r1), a1, a0);
+        cr.close_path();
+
+        let (t, p) = self.centre(ix);
+        if self.project(self.to_sphere(t, p), cellsize).2 > 0. {
+            let mut outline = self.samples(&Wall::Latitude(t0, p0, p1));
+            let mut back = self.samples(&Wall::Latitude(t1, p0, p1));
+            back.reverse();
+            outline.extend(back);
+            let points: Vec<(f64, f64)> = outline
+                .iter()
+                .map(|v| {
+                    let (x, y, _) = self.project(*v, cellsize);
+                    (x, y)
+                })
+                .collect();
+            polygon_path(cr, &points);
+        }
+    }
+
+    fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+        cr.set_line_width(1.0);
+
+        let equator = PI / 2.;
+        for wall in self.walls() {
+            match wall {
+                Wall::Latitude(t, p0, p1) => {
+                    for north in [true, false] {
+                        if (north && t > equator + 1e-9) || (!north && t < equator - 1e-9) {
+                            continue;
+                        }
+                        let ((cx, cy), r, _) = self.to_net(t, 0., north, cellsize);
+                        cr.new_sub_path();
+                        match north {
+                            true => cr.arc(cx, cy, r, p0, p1),
+                            false => cr.arc(cx, cy, r, -p1, -p0),
+                        }
+                    }
+                }
+                Wall::Meridian(p, t0, t1) => {
+                    let north = t1 <= equator + 1e-9;
+                    let ((cx, cy), r0, a) = self.to_net(t0, p, north, cellsize);
+                    let (_, r1, _) = self.to_net(t1, p, north, cellsize);
+                    cr.move_to(cx + r0 * a.cos(), cy + r0 * a.sin());
+                    cr.line_to(cx + r1 * a.cos(), cy + r1 * a.sin());
+                }
+            }
+            self.draw_projected(cr, &self.samples(&wall), cellsize);
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        // outline of the projected sphere
+        let (cx, cy) = self.centres(cellsize)[2];
+        cr.new_sub_path();
+        cr.arc(cx, cy, self.height as f64 * cellsize, 0., 2. * PI);
+        cr.stroke().expect("error while drawing stroke");
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn draw_pathfind(
+        &self,
+        w: &DrawingArea,
+        cr: &Context,
+        step_state: &DijkstraStep,
+        cellsize: f64,
+    ) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let path = step_state.path_to(max_idx);
+
+        for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 {
+                continue;
+            }
+            let intensity = (max_length - c.path_length) as f64 / max_length as f64;
+            cr.set_source_rgb(intensity, 0.5 + intensity / 2., intensity);
+            let (t0, t1, p0, p1) = self.bounds(i);
+            let north = self.is_north(i);
+
+            let ((cx, cy), r0, _) = self.to_net(t0, 0., north, cellsize);
+            let (_, r1, _) = self.to_net(t1, 0., north, cellsize);
+            let (a0, a1) = if north { (p0, p1) } else { (-p1, -p0) };
+            cr.new_sub_path();
+            cr.arc(cx, cy, r0.max(r1), a0, a1);
+            cr.arc_negative(cx, cy, r0.min(r1), a1, a0);
+            cr.close_path();
+            cr.fill().expect("error while drawing stroke");
+
+            let (t, p) = self.centre(i);
+            if self.project(self.to_sphere(t, p), cellsize).2 > 0. {
+                let mut outline = self.samples(&Wall::Latitude(t0, p0, p1));
+                let mut back = self.samples(&Wall::Latitude(t1, p0, p1));
+                back.reverse();
+                outline.extend(back);
+                for (k, v) in outline.iter().enumerate() {
+                    let (x, y, _) = self.project(*v, cellsize);
+                    match k {
+                        0 => cr.move_to(x, y),
+                        _ => cr.line_to(x, y),
+                    }
+                }
+                cr.close_path();
+                cr.fill_preserve().expect("error while drawing stroke");
+                cr.set_line_width(0.5);
+                cr.stroke().expect("error while drawing stroke");
+            }
+        }
+
+        let net_point = |ix: usize| {
+            let (t, p) = self.centre(ix);
+            let ((cx, cy), r, a) = self.to_net(t, p, self.is_north(ix), cellsize);
+            (cx + r * a.cos(), cy + r * a.sin())
+        };
+        cr.set_line_width(1.0);
+        cr.set_source_rgb(0., 0., 0.);
+        for ix in [path.first(), path.last()].into_iter().flatten() {
+            let (x, y) = net_point(*ix);
+            cr.new_sub_path();
+            cr.arc(x, y, cellsize / 2., 0., 2. * PI);
+            cr.stroke().expect("error while drawing stroke");
+        }
+
+        cr.set_source_rgb(1., 0., 0.);
+        cr.set_line_width(4.0);
+        for pair in path.windows(2) {
+            // crossing the equator leaves one disc for the other
+            if self.is_north(pair[0]) == self.is_north(pair[1]) {
+                let (x1, y1) = net_point(pair[0]);
+                let (x2, y2) = net_point(pair[1]);
+                cr.move_to(x1, y1);
+                cr.line_to(x2, y2);
+            }
+
+            let (t1, p1) = self.centre(pair[0]);
+            let (t2, p2) = self.centre(pair[1]);
+            let (v1, v2) = (self.to_sphere(t1, p1), self.to_sphere(t2, p2));
+            let points: Vec<[f64; 3]> = (0..=8)
+                .map(|i| {
+                    let k = i as f64 / 8.;
+                    let v: [f64; 3] = std::array::from_fn(|j| v1[j] + (v2[j] - v1[j]) * k);
+                    let len = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                    v.map(|x| x / len)
+                })
+                .collect();
+            self.draw_projected(cr, &points, cellsize);
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    /// Row and column of the cell under (x, y) in one of the hemisphere discs.
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let x = x * canvas_width / w.allocated_width() as f64;
+        let y = y * canvas_height / w.allocated_height() as f64;
+
+        let [n, s, _] = self.centres(cellsize);
+        for (north, (cx, cy)) in [(true, n), (false, s)] {
+            let (dx, dy) = (x - cx, y - cy);
+            let ring = ((dx * dx + dy * dy).sqrt() / cellsize).floor() as usize;
+            if ring >= self.height {
+                continue;
+            }
+            let (row, theta) = match north {
+                true => (ring, dy.atan2(dx)),
+                false => (2 * self.height - 1 - ring, -dy.atan2(dx)),
+            };
+            let first = self.cells.iter().find(|c| c.row == row)?;
+            let col = (theta.rem_euclid(2. * PI) / (2. * PI / first.columns as f64)).floor();
+            return Some((row, (col as usize).min(first.columns - 1)));
+        }
+        None
+    }
+}
@@ -0,0 +1,205 @@
+use crate::grid::{AbstractCell, AbstractGrid, CellPosition};
+use crate::solve;
+use std::fmt::{Display, Error, Formatter};
+
+/// Cells with two passages count as straight when the passages leave in directions at least
+/// this far from each other, measured as the cosine of the angle between them.
+const STRAIGHT_COS: f64 = -0.9;
+
+/// Texture of a maze, for comparing generators. Counts are over active cells; lengths are
+/// in steps between cells.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MazeStats {
+    pub cells: usize,
+    /// Cells with a single passage.
+    pub dead_ends: usize,
+    /// Cells with three or more passages.
+    pub junctions: usize,
+    /// Cells with two passages leaving on opposite sides, judged by cell positions.
+    pub straights: usize,
+    /// Cells with two passages that bend.
+    pub turns: usize,
+    /// Average length of the passages between dead ends and junctions.
+    pub average_corridor: f64,
+    /// Longest shortest path between two cells, exact for perfect mazes.
+    pub longest_path: usize,
+    /// Length of the solution `solve::solve_with_longest_path` draws.
+    pub solution: usize,
+    /// Solution length over the straight distance between its ends; None when the ends
+    /// are drawn on top of each other.
+    pub tortuosity: Option<f64>,
+    /// Share of cells inside corridors rather than at dead ends and junctions: high when
+    /// passages wind a long way before branching, low for many short dead ends.
+    pub river: f64,
+    /// Independent loops, 0 for a perfect maze; braiding adds them.
+    pub loops: usize,
+}
+
+impl MazeStats {
+    pub fn dead_end_ratio(&self) -> f64 {
+        ratio(self.dead_ends, self.cells)
+    }
+
+    /// One JSON object with all the figures.
+    pub fn to_json(&self) -> String {
+        let tortuosity = match self.tortuosity {
+            Some(t) => format!("{:.4}", t),
+            None => "null".to_string(),
+        };
+        format!(
+            "{{\"cells\":{},\"dead_ends\":{},\"dead_end_ratio\":{:.4},\"junctions\":{},\"straights\":{},\"turns\":{},\"average_corridor\":{:.4},\"longest_path\":{},\"solution\":{},\"tortuosity\":{},\"river\":{:.4},\"loops\":{}}}",
+            self.cells,
+            self.dead_ends,
+            self.dead_end_ratio(),
+            self.junctions,
+            self.straights,
+            self.turns,
+            self.average_corridor,
+            self.longest_path,
+            self.solution,
+            tortuosity,
+            self.river,
+            self.loops
+        )
+    }
+}
+
+/// One figure per line, for the GUI.
+impl Display for MazeStats {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        writeln!(f, "cells: {}", self.cells)?;
+        writeln!(
+            f,
+            "dead ends: {} ({:.1}%)",
+            self.dead_ends,
+            100. * self.dead_end_ratio()
+        )?;
+        writeln!(f, "junctions: {}", self.junctions)?;
+        writeln!(f, "straights: {}", self.straights)?;
+        writeln!(f, "turns: {}", self.turns)?;
+        writeln!(f, "average corridor: {:.2}", self.average_corridor)?;
+        writeln!(f, "longest path: {}", self.longest_path)?;
+        writeln!(f, "solution: {}", self.solution)?;
+        match self.tortuosity {
+            Some(t) => writeln!(f, "tortuosity: {:.2}", t)?,
+            None => writeln!(f, "tortuosity: -")?,
+        }
+        writeln!(f, "river: {:.2}", self.river)?;
+        write!(f, "loops: {}", self.loops)
+    }
+}
+
+fn ratio(a: usize, b: usize) -> f64 {
+    if b == 0 {
+        0.
+    } else {
+        a as f64 / b as f64
+    }
+}
+
+/// Cell reached last from `start`, and how far away it is.
+fn farthest(distance: &[Option<usize>]) -> (usize, usize) {
+    distance
+        .iter()
+        .enumerate()
+        .filter_map(|(ix, d)| d.map(|d| (ix, d)))
+        .max_by_key(|&(_, d)| d)
+        .unwrap_or((0, 0))
+}
+
+/// Independent loops in the passages: the links a spanning forest of the active cells leaves
+/// out, 0 for a perfect maze.
+pub fn loops<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(g: &T) -> usize {
+    let active: Vec<usize> = (0..g.len()).filter(|&ix| g.is_active(ix)).collect();
+    let link_count = active.iter().map(|&ix| g.links(ix).len()).sum::<usize>() / 2;
+    let mut seen = vec![false; g.len()];
+    let mut components = 0;
+    for &ix in &active {
+        if seen[ix] {
+            continue;
+        }
+        components += 1;
+        seen[ix] = true;
+        let mut stack = vec![ix];
+        while let Some(cur) = stack.pop() {
+            for n in g.links(cur) {
+                if !seen[n] {
+                    seen[n] = true;
+                    stack.push(n);
+                }
+            }
+        }
+    }
+    (link_count + components).saturating_sub(active.len())
+}
+
+/// Works out the statistics of a generated maze.
+pub fn analyze<C: AbstractCell, T: AbstractGrid<C> + CellPosition>(g: &T) -> MazeStats {
+    let active: Vec<usize> = (0..g.len()).filter(|&ix| g.is_active(ix)).collect();
+    let links: Vec<Vec<usize>> = (0..g.len())
+        .map(|ix| g.links(ix).into_iter().collect())
+        .collect();
+    let mut stats = MazeStats {
+        cells: active.len(),
+        ..MazeStats::default()
+    };
+
+    for &ix in &active {
+        match links[ix][..] {
+            [_] => stats.dead_ends += 1,
+            [a, b] => {
+                let (x, y) = g.position(ix);
+                let (ax, ay) = g.position(a);
+                let (bx, by) = g.position(b);
+                let (ux, uy, vx, vy) = (ax - x, ay - y, bx - x, by - y);
+                let cos = (ux * vx + uy * vy) / (ux.hypot(uy) * vx.hypot(vy));
+                if cos < STRAIGHT_COS {
+                    stats.straights += 1;
+                } else {
+                    stats.turns += 1;
+                }
+            }
+            [] => {}
+            _ => stats.junctions += 1,
+        }
+    }
+
+    // every corridor is walked once from each end
+    let (mut corridors, mut steps) = (0, 0);
+    for &ix in active.iter().filter(|&&ix| links[ix].len() != 2) {
+        for &first in &links[ix] {
+            let (mut from, mut at) = (ix, first);
+            steps += 1;
+            while links[at].len() == 2 && at != ix {
+                let next = if links[at][0] == from {
+                    links[at][1]
+                } else {
+                    links[at][0]
+                };
+                (from, at) = (at, next);
+                steps += 1;
+            }
+            corridors += 1;
+        }
+    }
+    stats.average_corridor = ratio(steps, corridors);
+    let corridor_cells = stats.straights + stats.turns;
+    stats.river = ratio(corridor_cells, stats.cells);
+
+    stats.loops = loops(g);
+
+    if let Some(&start) = active.first() {
+        let (end, _) = farthest(&solve::distances(g, &[start]));
+        stats.longest_path = farthest(&solve::distances(g, &[end])).1;
+    }
+
+    let step = solve::solve_with_longest_path(g);
+    let path = step.path_to(step.farthest());
+    if let (Some(&first), Some(&last)) = (path.first(), path.last()) {
+        stats.solution = path.len() - 1;
+        let ((x1, y1), (x2, y2)) = (g.position(first), g.position(last));
+        let distance = (x2 - x1).hypot(y2 - y1);
+        stats.tortuosity = (distance > 0.).then(|| stats.solution as f64 / distance);
+    }
+    stats
+}
@@ -0,0 +1,583 @@
+use crate::delta::{self, DeltaGrid};
+use crate::grid::{AbstractCell, AbstractGrid, CompassDirections, RectangularGrid};
+use crate::hexagonal::HexagonalGrid;
+use crate::rectangle::{RegularGrid, Wrap};
+use crate::solve::DijkstraStep;
+use std::fmt::{Display, Error, Formatter};
+use std::str::FromStr;
+
+/// What to print inside cells, where the renderer has room for it.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum CellMarks {
+    Nothing,
+    Solution,
+    Distances,
+}
+
+pub trait TextDrawable {
+    fn to_text(&self, step: &DijkstraStep, marks: &CellMarks) -> String;
+}
+
+struct TextCanvas {
+    lines: Vec<Vec<char>>,
+}
+
+impl TextCanvas {
+    fn new(height: usize, width: usize) -> TextCanvas {
+        TextCanvas {
+            lines: vec![vec![' '; width]; height],
+        }
+    }
+
+    fn put(&mut self, y: usize, x: usize, s: &str) {
+        for (i, c) in s.chars().enumerate() {
+            self.lines[y][x + i] = c;
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut res = String::new();
+        for line in &self.lines {
+            let line: String = line.iter().collect();
+            res.push_str(line.trim_end());
+            res.push('\n');
+        }
+        res
+    }
+}
+
+/// Text for the inside of every cell, padded to `width` characters.
+fn cell_marks(step: &DijkstraStep, marks: &CellMarks, width: usize) -> Vec<String> {
+    let blank = " ".repeat(width);
+    match marks {
+        CellMarks::Nothing => vec![blank; step.cell_weights.len()],
+        CellMarks::Solution => {
+            let mut res = vec![blank; step.cell_weights.len()];
+            let path = step.path_to(step.farthest());
+            for (i, &ix) in path.iter().enumerate() {
+                let mark = if i == 0 {
+                    "S"
+                } else if i == path.len() - 1 {
+                    "E"
+                } else {
+                    "•"
+                };
+                res[ix] = format!("{:^width$}", mark, width = width);
+            }
+            res
+        }
+        CellMarks::Distances => step
+            .cell_weights
+            .iter()
+            .map(|c| match c.path_length {
+                d if d < 0 => blank.clone(),
+                d => format!("{:>width$}", d % 10i32.pow(width as u32), width = width),
+            })
+            .collect(),
+    }
+}
+
+fn linked<C: AbstractCell + ?Sized, T: AbstractGrid<C> + ?Sized>(
+    g: &T,
+    ix: usize,
+    other: Option<usize>,
+) -> bool {
+    other
+        .map(|o| g.cell(ix).links().contains(&o))
+        .unwrap_or(false)
+}
+
+// Box drawing junctions indexed by up << 3 | down << 2 | left << 1 | right.
+const JUNCTIONS: [char; 16] = [
+    ' ', '╶', '╴', '─', '╷', '┌', '┐', '┬', '╵', '└', '┘', '┴', '│', '├', '┤', '┼',
+];
+
+impl RegularGrid {
+    /// Whether there is a wall above cell (row, col); row may be `height` for the bottom border.
+    /// Walls are only drawn next to active cells, masked areas stay blank. Borders that wrap
+    /// are open where a cell links across them.
+    fn wall_above(&self, row: usize, col: usize) -> bool {
+        let above = row > 0 && self.mask.is_on(row - 1, col);
+        let below = self.mask.is_on(row, col);
+        if (above || row == 0) && below {
+            !linked(self, self.ix(row, col), self.north_ix(self.ix(row, col)))
+        } else if row == self.height && above {
+            let ix = self.ix(row - 1, col);
+            !linked(self, ix, self.south_ix(ix))
+        } else {
+            above || below
+        }
+    }
+
+    /// Whether there is a wall left of cell (row, col); col may be `width` for the right border.
+    fn wall_left(&self, row: usize, col: usize) -> bool {
+        let left = col > 0 && self.mask.is_on(row, col - 1);
+        let right = self.mask.is_on(row, col);
+        if (left || col == 0) && right {
+            !linked(self, self.ix(row, col), self.west_ix(self.ix(row, col)))
+        } else if col == self.width && left {
+            let ix = self.ix(row, col - 1);
+            !linked(self, ix, self.east_ix(ix))
+        } else {
+            left || right
+        }
+    }
+
+    fn junction(&self, row: usize, col: usize) -> char {
+        let up = row > 0 && self.wall_left(row - 1, col);
+        let down = row < self.height && self.wall_left(row, col);
+        let left = col > 0 && self.wall_above(row, col - 1);
+        let right = col < self.width && self.wall_above(row, col);
+        JUNCTIONS[(up as usize) << 3 | (down as usize) << 2 | (left as usize) << 1 | right as usize]
+    }
+}
+
+impl TextDrawable for RegularGrid {
+    fn to_text(&self, step: &DijkstraStep, marks: &CellMarks) -> String {
+        let marks = cell_marks(step, marks, 3);
+        let mut res = String::new();
+        for i in 0..=self.height {
+            for j in 0..=self.width {
+                res.push(self.junction(i, j));
+                if j < self.width {
+                    res.push_str(if self.wall_above(i, j) {
+                        "───"
+                    } else {
+                        "   "
+                    });
+                }
+            }
+            res.push('\n');
+            if i == self.height {
+                break;
+            }
+            for j in 0..=self.width {
+                res.push(if self.wall_left(i, j) { '│' } else { ' ' });
+                if j < self.width {
+                    res.push_str(&marks[self.ix(i, j)]);
+                }
+            }
+            res.push('\n');
+        }
+        res.lines()
+            .map(|l| l.trim_end().to_owned() + "\n")
+            .collect()
+    }
+}
+
+// Flat-topped hexes, each 4 characters wide and 2 lines tall, sharing side characters with
+// their neighbours; odd columns sit one line lower:
+//  __    __
+// /  \__/  \
+// \__/  \__/
+//    \__/
+impl TextDrawable for HexagonalGrid {
+    fn to_text(&self, step: &DijkstraStep, marks: &CellMarks) -> String {
+        let show_marks = *marks != CellMarks::Nothing;
+        let marks = cell_marks(step, marks, 2);
+        let mut canvas = TextCanvas::new(2 * self.height + 2, 3 * self.width + 1);
+        for (ix, mark) in marks.iter().enumerate() {
+            if !self.is_active(ix) {
+                continue;
+            }
+            let (row, col) = (self.cells[ix].row, self.cells[ix].col);
+            let x = 3 * col;
+            let y = 2 * row + col % 2;
+            let walls = self.walls(ix);
+            if walls[0] {
+                canvas.put(y, x + 1, "__");
+            }
+            if walls[1] {
+                canvas.put(y + 1, x + 3, "\\");
+            }
+            if walls[2] {
+                canvas.put(y + 2, x + 3, "/");
+            }
+            if walls[3] {
+                canvas.put(y + 2, x + 1, "__");
+            }
+            if walls[4] {
+                canvas.put(y + 2, x, "\\");
+            }
+            if walls[5] {
+                canvas.put(y + 1, x, "/");
+            }
+            if show_marks {
+                canvas.put(y + 1, x + 1, mark);
+            }
+        }
+        canvas.render()
+    }
+}
+
+// Triangles two characters apart, each row taking two lines below a shared base line:
+//   /\  /\
+//  /__\/__\
+// Cell marks are not drawn, up-pointing triangles have no room for them.
+impl TextDrawable for DeltaGrid {
+    fn to_text(&self, _step: &DijkstraStep, _marks: &CellMarks) -> String {
+        let mut canvas = TextCanvas::new(2 * self.height + 1, 2 * self.width + 2);
+        for ix in (0..self.len()).filter(|&ix| self.is_active(ix)) {
+            let (row, col) = (self.cells[ix].row, self.cells[ix].col);
+            let x = 2 * col;
+            let y = 2 * row;
+            let west = !linked(self, ix, self.west_ix(ix));
+            let east = !linked(self, ix, self.east_ix(ix));
+            if delta::is_up(row, col) {
+                if west {
+                    canvas.put(y + 1, x + 1, "/");
+                    canvas.put(y + 2, x, "/");
+                }
+                if east {
+                    canvas.put(y + 1, x + 2, "\\");
+                    canvas.put(y + 2, x + 3, "\\");
+                }
+                if !linked(self, ix, self.south_ix(ix)) {
+                    canvas.put(y + 2, x + 1, "__");
+                }
+            } else {
+                if west {
+                    canvas.put(y + 1, x, "\\");
+                    canvas.put(y + 2, x + 1, "\\");
+                }
+                if east {
+                    canvas.put(y + 1, x + 3, "/");
+                    canvas.put(y + 2, x + 2, "/");
+                }
+                if !linked(self, ix, self.north_ix(ix)) {
+                    canvas.put(y, x + 1, "__");
+                }
+            }
+        }
+        canvas.render()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter) -> Result<(), Error> {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+fn parse_error(line: usize, column: usize, message: &str) -> ParseError {
+    ParseError {
+        line: line + 1,
+        column: column + 1,
+        message: message.to_string(),
+    }
+}
+
+/// Checks that the openings in the borders `wrap` joins face each other: left and right
+/// openings in the same rows for cylinders and tori (mirrored rows for Moebius strips), top
+/// and bottom openings in the same columns for tori. Openings in other borders are plain
+/// entrances and exits.
+fn check_seams(
+    wrap: &Wrap,
+    top: &[bool],
+    bottom: &[bool],
+    left: &[bool],
+    right: &[bool],
+) -> Result<(), ParseError> {
+    let height = left.len();
+    let across: Vec<bool> = match wrap {
+        Wrap::None => return Ok(()),
+        Wrap::Moebius => right.iter().rev().copied().collect(),
+        Wrap::Cylinder | Wrap::Torus => right.to_vec(),
+    };
+    if let Some(row) = (0..height).find(|&r| left[r] != across[r]) {
+        return Err(parse_error(
+            2 * row + 1,
+            0,
+            "border opening has no match across",
+        ));
+    }
+    if *wrap == Wrap::Torus {
+        if let Some(col) = (0..top.len()).find(|&c| top[c] != bottom[c]) {
+            return Err(parse_error(
+                0,
+                4 * col + 1,
+                "border opening has no match below",
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn is_corner(c: char) -> bool {
+    c == '+' || JUNCTIONS.contains(&c)
+}
+
+/// Reads walls back from the `+---+` art written by `Display for RegularGrid`, or from the
+/// box drawing variant written by `to_text`. Cell contents are ignored, so mazes printed
+/// with solution or distance marks parse too. Openings in the outer border are entrances
+/// and exits; see `RegularGrid::parse_wrapped` for wrapped mazes.
+impl FromStr for RegularGrid {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<RegularGrid, ParseError> {
+        RegularGrid::parse_wrapped(s, Wrap::None)
+    }
+}
+
+impl RegularGrid {
+    /// Parses maze art like `from_str`, joining the borders `wrap` joins: openings there
+    /// are passages across the seam and must have a matching opening on the other side.
+    pub fn parse_wrapped(s: &str, wrap: Wrap) -> Result<RegularGrid, ParseError> {
+        let lines: Vec<Vec<char>> = s
+            .trim_end()
+            .lines()
+            .map(|l| l.trim_end().chars().collect())
+            .collect();
+        if lines.is_empty() || lines[0].is_empty() {
+            return Err(parse_error(0, 0, "empty maze"));
+        }
+        if lines.len() < 3 || lines.len().is_multiple_of(2) {
+            let last = lines.len() - 1;
+            return Err(parse_error(last, 0, "expected a closing border line"));
+        }
+        // the top line only loses its last corner to trimming when both borders there wrap
+        let mut border = lines[0].len();
+        if !lines[0].last().is_some_and(|&c| is_corner(c)) {
+            let longest = lines.iter().map(|l| l.len()).max().unwrap_or(0);
+            border = 4 * longest.saturating_sub(1).div_ceil(4) + 1;
+        }
+        if border < 5 || border % 4 != 1 {
+            return Err(parse_error(
+                0,
+                lines[0].len(),
+                "border line has a partial cell",
+            ));
+        }
+        let height = lines.len() / 2;
+        let width = border / 4;
+        let mut g = RegularGrid::new(height, width);
+        let (mut top, mut bottom) = (vec![false; width], vec![false; width]);
+        let (mut left, mut right) = (vec![false; height], vec![false; height]);
+
+        for (y, line) in lines.iter().enumerate() {
+            if line.len() > 4 * width + 1 {
+                return Err(parse_error(
+                    y,
+                    4 * width + 1,
+                    "line is longer than the border",
+                ));
+            }
+            let at = |x: usize| *line.get(x).unwrap_or(&' ');
+            let row = y / 2;
+            if y % 2 == 0 {
+                for x in (0..=4 * width).step_by(4) {
+                    if at(x) != ' ' && !is_corner(at(x)) {
+                        return Err(parse_error(y, x, "expected a corner"));
+                    }
+                }
+                for col in 0..width {
+                    let x = 4 * col + 1;
+                    let segment: String = (x..x + 3).map(at).collect();
+                    let open = match segment.as_str() {
+                        "---" | "───" => false,
+                        "   " => true,
+                        _ => return Err(parse_error(y, x, "expected a wall or an opening")),
+                    };
+                    if row == 0 {
+                        top[col] = open;
+                    } else if row == height {
+                        bottom[col] = open;
+                    } else if open {
+                        g.link(g.ix(row - 1, col), g.ix(row, col));
+                    }
+                }
+            } else {
+                for col in 0..=width {
+                    let x = 4 * col;
+                    let open = match at(x) {
+                        '|' | '│' => false,
+                        ' ' => true,
+                        _ => return Err(parse_error(y, x, "expected a wall or an opening")),
+                    };
+                    if col == 0 {
+                        left[row] = open;
+                    } else if col == width {
+                        right[row] = open;
+                    } else if open {
+                        g.link(g.ix(row, col - 1), g.ix(row, col));
+                    }
+                }
+            }
+        }
+
+        check_seams(&wrap, &top, &bottom, &left, &right)?;
+        g.wrap = wrap;
+        let mut seams = Vec::new();
+        for row in 0..height {
+            let (first, last) = (g.ix(row, 0), g.ix(row, width - 1));
+            seams.extend(g.west_ix(first).filter(|_| left[row]).map(|n| (first, n)));
+            seams.extend(g.east_ix(last).filter(|_| right[row]).map(|n| (last, n)));
+        }
+        for col in 0..width {
+            let (first, last) = (g.ix(0, col), g.ix(height - 1, col));
+            seams.extend(g.north_ix(first).filter(|_| top[col]).map(|n| (first, n)));
+            seams.extend(g.south_ix(last).filter(|_| bottom[col]).map(|n| (last, n)));
+        }
+        for (ix, across) in seams {
+            g.link(ix, across);
+        }
+        Ok(g)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::draw_utils::{make_tha_maze, GenerationType};
+    use crate::solve;
+
+    fn assert_same_links(a: &RegularGrid, b: &RegularGrid) {
+        assert_eq!((a.height, a.width), (b.height, b.width));
+        for ix in 0..a.len() {
+            assert_eq!(a.links(ix), b.links(ix), "links of cell {}", ix);
+        }
+    }
+
+    fn maze() -> RegularGrid {
+        let mut g = RegularGrid::new(6, 9);
+        make_tha_maze(&GenerationType::RecursiveBacktracker, &mut g, 60);
+        g
+    }
+
+    #[test]
+    fn parses_ascii_art() {
+        let art = "\
++---+---+
+|       |
++---+   +
+|       |
++---+---+
+";
+        let g: RegularGrid = art.parse().unwrap();
+        assert_eq!((g.height, g.width), (2, 2));
+        assert_eq!(g.links(0), [1].into());
+        assert_eq!(g.links(1), [0, 3].into());
+        assert_eq!(g.links(2), [3].into());
+    }
+
+    #[test]
+    fn round_trips_display() {
+        let g = maze();
+        assert_same_links(&g, &g.to_string().parse().unwrap());
+    }
+
+    #[test]
+    fn round_trips_box_drawing() {
+        let g = maze();
+        let step = solve::solve_with_longest_path(&g);
+        for marks in [
+            CellMarks::Nothing,
+            CellMarks::Solution,
+            CellMarks::Distances,
+        ] {
+            let text = g.to_text(&step, &marks);
+            assert_same_links(&g, &text.parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn round_trips_wrapped_mazes() {
+        for wrap in [Wrap::Cylinder, Wrap::Torus] {
+            let mut g = RegularGrid::new(5, 7);
+            g.wrap = wrap.clone();
+            make_tha_maze(&GenerationType::RecursiveBacktracker, &mut g, 60);
+            let step = solve::solve_with_longest_path(&g);
+            for text in [g.to_string(), g.to_text(&step, &CellMarks::Nothing)] {
+                let parsed = RegularGrid::parse_wrapped(&text, wrap.clone()).unwrap();
+                assert_eq!(parsed.wrap, wrap);
+                assert_same_links(&g, &parsed);
+            }
+        }
+    }
+
+    #[test]
+    fn reads_moebius_seams() {
+        // a single passage across the seam joins the top left and bottom right cells
+        let mut g = RegularGrid::new(3, 3);
+        g.wrap = Wrap::Moebius;
+        for (a, b) in [
+            (0, 1),
+            (1, 2),
+            (2, 5),
+            (5, 4),
+            (4, 3),
+            (3, 6),
+            (6, 7),
+            (7, 8),
+            (0, 8),
+        ] {
+            g.link(a, b);
+        }
+        let step = solve::solve_with_longest_path(&g);
+        for text in [g.to_string(), g.to_text(&step, &CellMarks::Nothing)] {
+            let parsed = RegularGrid::parse_wrapped(&text, Wrap::Moebius).unwrap();
+            assert_eq!(parsed.wrap, Wrap::Moebius);
+            assert_same_links(&g, &parsed);
+        }
+    }
+
+    #[test]
+    fn reads_entrance_and_exit_as_openings() {
+        // entrance at the top of the first column, exit at the bottom of the last one
+        let art = "\
++   +---+
+|       |
++---+   +
+|       |
++---+   +
+";
+        let g: RegularGrid = art.parse().unwrap();
+        assert_eq!(g.wrap, Wrap::None);
+        assert_eq!(g.links(0), [1].into());
+        assert_eq!(g.links(1), [0, 3].into());
+        assert_eq!(g.links(3), [1, 2].into());
+    }
+
+    #[test]
+    fn one_sided_gaps_only_wrap_when_asked() {
+        // a gap in the left border of the first row and a wall opposite
+        let art = "\
++---+---+
+        |
++---+   +
+|       |
++---+---+
+";
+        let g: RegularGrid = art.parse().unwrap();
+        assert_eq!(g.wrap, Wrap::None);
+        assert_eq!(g.links(0), [1].into());
+        assert_eq!(g.links(1), [0, 3].into());
+        for wrap in [Wrap::Cylinder, Wrap::Moebius, Wrap::Torus] {
+            let err = RegularGrid::parse_wrapped(art, wrap).unwrap_err();
+            assert_eq!((err.line, err.column), (2, 1));
+        }
+    }
+
+    #[test]
+    fn reports_where_art_is_malformed() {
+        let err = "+---+\n|   |\n+-x-+\n".parse::<RegularGrid>().unwrap_err();
+        assert_eq!((err.line, err.column), (3, 2));
+        let err = "+---*\n|   |\n+---+\n".parse::<RegularGrid>().unwrap_err();
+        assert_eq!((err.line, err.column), (1, 5));
+        let err = "+---+\n|   #\n+---+\n".parse::<RegularGrid>().unwrap_err();
+        assert_eq!((err.line, err.column), (2, 5));
+        let err = "+---+\n|   |\n".parse::<RegularGrid>().unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+    }
+}
@@ -0,0 +1,321 @@
+use crate::grid::{AbstractGrid, CompassDirections};
+use crate::hexagonal::HexagonalGrid;
+use crate::rectangle::RegularGrid;
+use crate::solve::DijkstraStep;
+
+const TILE_WIDTH: usize = 32;
+const TILE_HEIGHT: usize = 32;
+// Flat-topped hex tiles: the top and bottom sides are half the tile width.
+const HEX_TILE_HEIGHT: usize = 28;
+
+pub struct TileOptions {
+    /// Cells become `block` x `block` tile squares that share their border tiles with
+    /// neighbouring cells, so 3 gives the classic one-tile walls and corridors.
+    pub block: usize,
+    pub floor_gid: u32,
+    pub wall_gid: u32,
+    /// Add "start" and "end" point objects at the ends of the longest path.
+    pub markers: bool,
+}
+
+impl Default for TileOptions {
+    fn default() -> TileOptions {
+        TileOptions {
+            block: 3,
+            floor_gid: 1,
+            wall_gid: 2,
+            markers: false,
+        }
+    }
+}
+
+pub struct TileMap {
+    pub width: usize,
+    pub height: usize,
+    /// Staggered hexagonal map (odd columns shifted down) instead of an orthogonal one.
+    pub hexagonal: bool,
+    pub tiles: Vec<u32>,
+    /// Named point objects at tile coordinates.
+    pub markers: Vec<(String, usize, usize)>,
+}
+
+fn markers(
+    step: &DijkstraStep,
+    tile_of: impl Fn(usize) -> (usize, usize),
+) -> Vec<(String, usize, usize)> {
+    let end = step.farthest();
+    let path = step.path_to(end);
+    let mut res = Vec::new();
+    if let Some(&start) = path.first() {
+        let (x, y) = tile_of(start);
+        res.push(("start".to_string(), x, y));
+        let (x, y) = tile_of(end);
+        res.push(("end".to_string(), x, y));
+    }
+    res
+}
+
+impl TileMap {
+    pub fn from_regular(g: &RegularGrid, step: &DijkstraStep, opts: &TileOptions) -> TileMap {
+        let span = opts.block.max(3) - 1;
+        let width = g.width * span + 1;
+        let height = g.height * span + 1;
+        let mut tiles = vec![opts.wall_gid; width * height];
+        let mut floor = |x: usize, y: usize| tiles[x + y * width] = opts.floor_gid;
+
+        for ix in (0..g.len()).filter(|&ix| g.is_active(ix)) {
+            let cell = g.cell(ix);
+            let (x0, y0) = (cell.col * span, cell.row * span);
+            for y in y0 + 1..y0 + span {
+                for x in x0 + 1..x0 + span {
+                    floor(x, y);
+                }
+            }
+            // Open the east and south borders; west and north are handled by the neighbour,
+            // except across the seams of wrapped grids.
+            let linked = |n: Option<usize>| n.is_some_and(|n| cell.links.contains(&n));
+            if linked(g.east_ix(ix)) {
+                for y in y0 + 1..y0 + span {
+                    floor(x0 + span, y);
+                }
+            }
+            if linked(g.south_ix(ix)) {
+                for x in x0 + 1..x0 + span {
+                    floor(x, y0 + span);
+                }
+            }
+            if cell.col == 0 && linked(g.west_ix(ix)) {
+                for y in y0 + 1..y0 + span {
+                    floor(x0, y);
+                }
+            }
+            if cell.row == 0 && linked(g.north_ix(ix)) {
+                for x in x0 + 1..x0 + span {
+                    floor(x, y0);
+                }
+            }
+        }
+
+        let centre = |ix: usize| {
+            let cell = g.cell(ix);
+            (cell.col * span + span / 2, cell.row * span + span / 2)
+        };
+        TileMap {
+            width,
+            height,
+            hexagonal: false,
+            tiles,
+            markers: if opts.markers {
+                markers(step, centre)
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    /// Hex cells are spread `block - 1` tiles apart along the hex axes; the tiles between two
+    /// linked cells become floor, everything else is wall.
+    pub fn from_hexagonal(g: &HexagonalGrid, step: &DijkstraStep, opts: &TileOptions) -> TileMap {
+        let span = (opts.block.max(3) - 1) as i64;
+        // odd-q offset coordinates to cube coordinates and back
+        let to_cube = |row: usize, col: usize| {
+            let (row, col) = (row as i64, col as i64);
+            (col, row - (col - (col & 1)) / 2)
+        };
+        let to_tile = |x: i64, z: i64| (x as usize, (z + (x - (x & 1)) / 2) as usize);
+        let cell_cube = |ix: usize| {
+            let (x, z) = to_cube(g.cell(ix).row, g.cell(ix).col);
+            (x * span, z * span)
+        };
+
+        let width = (g.width - 1) * span as usize + 1;
+        let height = (0..g.len())
+            .map(|ix| to_tile(cell_cube(ix).0, cell_cube(ix).1).1 + 1)
+            .max()
+            .unwrap_or(0);
+        let mut tiles = vec![opts.wall_gid; width * height];
+
+        for ix in (0..g.len()).filter(|&ix| g.is_active(ix)) {
+            let (x, z) = cell_cube(ix);
+            let (tx, ty) = to_tile(x, z);
+            tiles[tx + ty * width] = opts.floor_gid;
+            for &n in g.cell(ix).links.iter().filter(|&&n| n > ix) {
+                let (nx, nz) = cell_cube(n);
+                let (dx, dz) = ((nx - x) / span, (nz - z) / span);
+                for j in 1..span {
+                    let (tx, ty) = to_tile(x + j * dx, z + j * dz);
+                    tiles[tx + ty * width] = opts.floor_gid;
+                }
+            }
+        }
+
+        let centre = |ix: usize| {
+            let (x, z) = cell_cube(ix);
+            to_tile(x, z)
+        };
+        TileMap {
+            width,
+            height,
+            hexagonal: true,
+            tiles,
+            markers: if opts.markers {
+                markers(step, centre)
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    fn tile_height(&self) -> usize {
+        if self.hexagonal {
+            HEX_TILE_HEIGHT
+        } else {
+            TILE_HEIGHT
+        }
+    }
+
+    /// Pixel position of the centre of a tile, as Tiled places objects.
+    fn pixel_centre(&self, x: usize, y: usize) -> (usize, usize) {
+        let th = self.tile_height();
+        if self.hexagonal {
+            let px = x * TILE_WIDTH * 3 / 4 + TILE_WIDTH / 2;
+            let py = y * th + th / 2 + if x % 2 == 1 { th / 2 } else { 0 };
+            (px, py)
+        } else {
+            (x * TILE_WIDTH + TILE_WIDTH / 2, y * th + th / 2)
+        }
+    }
+
+    fn csv(&self) -> String {
+        let rows: Vec<String> = self
+            .tiles
+            .chunks(self.width)
+            .map(|row| {
+                row.iter()
+                    .map(|t| t.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .collect();
+        rows.join(",\n")
+    }
+
+    pub fn to_tmx(&self) -> String {
+        let th = self.tile_height();
+        let mut res = String::new();
+        res.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        res.push_str(
+            format!(
+                "<map version=\"1.10\" orientation=\"{}\" renderorder=\"right-down\" width=\"{}\" height=\"{}\" tilewidth=\"{}\" tileheight=\"{}\"",
+                if self.hexagonal { "hexagonal" } else { "orthogonal" },
+                self.width,
+                self.height,
+                TILE_WIDTH,
+                th
+            )
+            .as_str(),
+        );
+        if self.hexagonal {
+            res.push_str(
+                format!(
+                    " hexsidelength=\"{}\" staggeraxis=\"x\" staggerindex=\"odd\"",
+                    TILE_WIDTH / 2
+                )
+                .as_str(),
+            );
+        }
+        res.push_str(" infinite=\"0\">\n");
+        res.push_str(
+            format!(
+                " <tileset firstgid=\"1\" name=\"maze\" tilewidth=\"{}\" tileheight=\"{}\" tilecount=\"2\" columns=\"2\"/>\n",
+                TILE_WIDTH, th
+            )
+            .as_str(),
+        );
+        res.push_str(
+            format!(
+                " <layer id=\"1\" name=\"maze\" width=\"{}\" height=\"{}\">\n  <data encoding=\"csv\">\n{}\n  </data>\n </layer>\n",
+                self.width,
+                self.height,
+                self.csv()
+            )
+            .as_str(),
+        );
+        if !self.markers.is_empty() {
+            res.push_str(" <objectgroup id=\"2\" name=\"markers\">\n");
+            for (i, (name, x, y)) in self.markers.iter().enumerate() {
+                let (px, py) = self.pixel_centre(*x, *y);
+                res.push_str(
+                    format!(
+                        "  <object id=\"{}\" name=\"{}\" x=\"{}\" y=\"{}\">\n   <point/>\n  </object>\n",
+                        i + 1,
+                        name,
+                        px,
+                        py
+                    )
+                    .as_str(),
+                );
+            }
+            res.push_str(" </objectgroup>\n");
+        }
+        res.push_str("</map>\n");
+        res
+    }
+
+    pub fn to_json(&self) -> String {
+        let th = self.tile_height();
+        let mut layers = vec![format!(
+            "{{\"id\":1,\"name\":\"maze\",\"type\":\"tilelayer\",\"x\":0,\"y\":0,\"width\":{},\"height\":{},\"opacity\":1,\"visible\":true,\"data\":[{}]}}",
+            self.width,
+            self.height,
+            self.tiles
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        )];
+        if !self.markers.is_empty() {
+            let objects: Vec<String> = self
+                .markers
+                .iter()
+                .enumerate()
+                .map(|(i, (name, x, y))| {
+                    let (px, py) = self.pixel_centre(*x, *y);
+                    format!(
+                        "{{\"id\":{},\"name\":\"{}\",\"type\":\"\",\"point\":true,\"x\":{},\"y\":{},\"width\":0,\"height\":0,\"rotation\":0,\"visible\":true}}",
+                        i + 1,
+                        name,
+                        px,
+                        py
+                    )
+                })
+                .collect();
+            layers.push(format!(
+                "{{\"id\":2,\"name\":\"markers\",\"type\":\"objectgroup\",\"x\":0,\"y\":0,\"opacity\":1,\"visible\":true,\"draworder\":\"topdown\",\"objects\":[{}]}}",
+                objects.join(",")
+            ));
+        }
+        let hex_fields = if self.hexagonal {
+            format!(
+                "\"hexsidelength\":{},\"staggeraxis\":\"x\",\"staggerindex\":\"odd\",",
+                TILE_WIDTH / 2
+            )
+        } else {
+            String::new()
+        };
+        format!(
+            "{{\"type\":\"map\",\"version\":\"1.10\",\"orientation\":\"{}\",\"renderorder\":\"right-down\",\"infinite\":false,\"width\":{},\"height\":{},\"tilewidth\":{},\"tileheight\":{},{}\"nextlayerid\":{},\"nextobjectid\":{},\"tilesets\":[{{\"firstgid\":1,\"name\":\"maze\",\"tilewidth\":{},\"tileheight\":{},\"tilecount\":2,\"columns\":2}}],\"layers\":[{}]}}\n",
+            if self.hexagonal { "hexagonal" } else { "orthogonal" },
+            self.width,
+            self.height,
+            TILE_WIDTH,
+            th,
+            hex_fields,
+            layers.len() + 1,
+            self.markers.len() + 1,
+            TILE_WIDTH,
+            th,
+            layers.join(",")
+        )
+    }
+}
@@ -0,0 +1,328 @@
+use crate::draw_utils::{self, GtkDrawable};
+use crate::grid::{AbstractGrid, CellPosition, RectangularGrid};
+use crate::gtk::prelude::WidgetExt;
+use crate::mask::Mask;
+use crate::play::Move;
+use crate::rectangle::Cell;
+use crate::solve::DijkstraStep;
+use gtk::cairo::Context;
+use gtk::DrawingArea;
+use std::f64::consts::SQRT_2;
+
+/// Octagons and squares in a checkerboard: cells whose row and column add up to an even
+/// number are octagons, which also connect diagonally to each other, the rest are squares.
+#[derive(Clone)]
+pub struct UpsilonGrid {
+    pub height: usize,
+    pub width: usize,
+    pub cells: Vec<Cell>,
+    pub mask: Mask,
+}
+
+impl RectangularGrid for UpsilonGrid {
+    fn width(&self) -> usize {
+        self.width
+    }
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl UpsilonGrid {
+    #[allow(dead_code)]
+    pub fn new(rows: usize, cols: usize) -> UpsilonGrid {
+        UpsilonGrid::masked(Mask::new(rows, cols))
+    }
+
+    pub fn masked(mask: Mask) -> UpsilonGrid {
+        let mut cells = Vec::new();
+        for i in 0..mask.rows {
+            for j in 0..mask.cols {
+                cells.push(Cell::new(i, j));
+            }
+        }
+        UpsilonGrid {
+            height: mask.rows,
+            width: mask.cols,
+            cells,
+            mask,
+        }
+    }
+
+    pub fn is_octagon(&self, ix: usize) -> bool {
+        (self.cells[ix].row + self.cells[ix].col).is_multiple_of(2)
+    }
+
+    fn offset_ix(&self, ix: usize, drow: isize, dcol: isize) -> Option<usize> {
+        let row = self.cells[ix].row.wrapping_add_signed(drow);
+        let col = self.cells[ix].col.wrapping_add_signed(dcol);
+        self.ix_opt(row, col)
+    }
+
+    /// Neighbours clockwise from north, matching the sides of `corners`: all eight
+    /// directions for octagons, north, east, south and west for squares.
+    fn sides(&self, ix: usize) -> Vec<Option<usize>> {
+        let offsets: &[(isize, isize)] = if self.is_octagon(ix) {
+            &[
+                (-1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+                (1, 0),
+                (1, -1),
+                (0, -1),
+                (-1, -1),
+            ]
+        } else {
+            &[(-1, 0), (0, 1), (1, 0), (0, -1)]
+        };
+        offsets
+            .iter()
+            .map(|&(drow, dcol)| self.offset_ix(ix, drow, dcol))
+            .collect()
+    }
+
+    /// Walls of cell `ix` clockwise from north; true where there is no passage.
+    pub fn walls(&self, ix: usize) -> Vec<bool> {
+        let links = &self.cells[ix].links;
+        self.sides(ix)
+            .iter()
+            .map(|n| n.is_none_or(|n| !links.contains(&n)))
+            .collect()
+    }
+
+    /// Centre of a cell; `cellsize` is the distance between an octagon and a square next
+    /// to it.
+    fn centre(&self, ix: usize, cellsize: f64) -> (f64, f64) {
+        let margin = cellsize / SQRT_2;
+        (
+            margin + self.cells[ix].col as f64 * cellsize,
+            margin + self.cells[ix].row as f64 * cellsize,
+        )
+    }
+
+    /// Corners of a cell clockwise from the west end of its north side, so side i runs
+    /// towards neighbour i of `sides`.
+    fn corners(&self, ix: usize, cellsize: f64) -> Vec<(f64, f64)> {
+        let (cx, cy) = self.centre(ix, cellsize);
+        // octagons touch diagonally, so their apothem is half the diagonal distance; the
+        // squares fill what is left up to the next centre
+        let apothem = cellsize / SQRT_2;
+        let offsets = if self.is_octagon(ix) {
+            let half_side = cellsize * (2. - SQRT_2) / 2.;
+            vec![
+                (-half_side, -apothem),
+                (half_side, -apothem),
+                (apothem, -half_side),
+                (apothem, half_side),
+                (half_side, apothem),
+                (-half_side, apothem),
+                (-apothem, half_side),
+                (-apothem, -half_side),
+            ]
+        } else {
+            let half_side = cellsize - apothem;
+            vec![
+                (-half_side, -half_side),
+                (half_side, -half_side),
+                (half_side, half_side),
+                (-half_side, half_side),
+            ]
+        };
+        offsets
+            .into_iter()
+            .map(|(dx, dy)| (cx + dx, cy + dy))
+            .collect()
+    }
+
+    /// Whether (x, y) lies inside cell `ix`.
+    fn contains(&self, ix: usize, x: f64, y: f64, cellsize: f64) -> bool {
+        let (cx, cy) = self.centre(ix, cellsize);
+        let (dx, dy) = ((x - cx).abs(), (y - cy).abs());
+        let apothem = cellsize / SQRT_2;
+        if self.is_octagon(ix) {
+            let half_side = cellsize * (2. - SQRT_2) / 2.;
+            dx <= apothem && dy <= apothem && dx + dy <= apothem + half_side
+        } else {
+            dx <= cellsize - apothem && dy <= cellsize - apothem
+        }
+    }
+}
+
+impl CellPosition for UpsilonGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        self.centre(ix, 1.)
+    }
+}
+
+impl AbstractGrid<Cell> for UpsilonGrid {
+    fn neighbours(&self, ix: usize) -> Vec<usize> {
+        if !self.is_active(ix) {
+            return Vec::new();
+        }
+        self.sides(ix)
+            .into_iter()
+            .flatten()
+            .filter(|&n| self.is_active(n))
+            .collect()
+    }
+
+    fn is_active(&self, ix: usize) -> bool {
+        self.mask.is_on(self.cells[ix].row, self.cells[ix].col)
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn cell(&self, ix: usize) -> &Cell {
+        &self.cells[ix]
+    }
+
+    fn cell_mut(&mut self, ix: usize) -> &mut Cell {
+        &mut self.cells[ix]
+    }
+}
+
+impl GtkDrawable for UpsilonGrid {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        let margin = 2. * cellsize / SQRT_2;
+        (
+            (self.width - 1) as f64 * cellsize + margin,
+            (self.height - 1) as f64 * cellsize + margin,
+        )
+    }
+
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        let directions: &[Move] = if self.is_octagon(ix) {
+            &[
+                Move::North,
+                Move::NorthEast,
+                Move::East,
+                Move::SouthEast,
+                Move::South,
+                Move::SouthWest,
+                Move::West,
+                Move::NorthWest,
+            ]
+        } else {
+            &[Move::North, Move::East, Move::South, Move::West]
+        };
+        let side = directions.iter().position(|&d| d == towards)?;
+        self.sides(ix)[side].filter(|n| self.cells[ix].links.contains(n))
+    }
+
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        draw_utils::polygon_path(cr, &self.corners(ix, cellsize));
+    }
+
+    fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+        cr.set_line_width(1.0);
+
+        for ix in (0..self.len()).filter(|&ix| self.is_active(ix)) {
+            let corners = self.corners(ix, cellsize);
+            for (i, wall) in self.walls(ix).into_iter().enumerate() {
+                if wall {
+                    let (from, to) = (corners[i], corners[(i + 1) % corners.len()]);
+                    cr.move_to(from.0, from.1);
+                    cr.line_to(to.0, to.1);
+                }
+            }
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn draw_maze_inset(&self, w: &DrawingArea, cr: &Context, cellsize: f64, inset: f64) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+
+        for ix in (0..self.len()).filter(|&ix| self.is_active(ix)) {
+            let open: Vec<bool> = self.walls(ix).iter().map(|wall| !wall).collect();
+            let centre = self.centre(ix, cellsize);
+            draw_utils::draw_inset_polygon(cr, &self.corners(ix, cellsize), centre, &open, inset);
+        }
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn draw_pathfind(
+        &self,
+        w: &DrawingArea,
+        cr: &Context,
+        step_state: &DijkstraStep,
+        cellsize: f64,
+    ) {
+        cr.save().expect("error while saving coords");
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let scalex = w.allocated_width() as f64 / canvas_width;
+        let scaley = w.allocated_height() as f64 / canvas_height;
+        cr.scale(scalex, scaley);
+
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let path = step_state.path_to(max_idx);
+
+        for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 {
+                continue;
+            }
+            let intensity = (max_length - c.path_length) as f64 / max_length as f64;
+            cr.set_source_rgb(intensity, 0.5 + intensity / 2., intensity);
+            let corners = self.corners(i, cellsize);
+            cr.move_to(corners[0].0, corners[0].1);
+            for (x, y) in &corners[1..] {
+                cr.line_to(*x, *y);
+            }
+            cr.close_path();
+            cr.fill().expect("error while drawing stroke");
+        }
+
+        cr.set_source_rgb(1., 0., 0.);
+        cr.set_line_width(4.0);
+        for (i, ix) in path.iter().enumerate() {
+            let (x, y) = self.centre(*ix, cellsize);
+            match i {
+                0 => cr.move_to(x, y),
+                _ => cr.line_to(x, y),
+            }
+        }
+        cr.stroke().expect("error while drawing stroke");
+
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let (canvas_width, canvas_height) = self.canvas_size(cellsize);
+        let x = x * canvas_width / w.allocated_width() as f64;
+        let y = y * canvas_height / w.allocated_height() as f64;
+
+        // the cell is within one row and column of the closest centre
+        let margin = cellsize / SQRT_2;
+        let col = ((x - margin) / cellsize).round() as i64;
+        let row = ((y - margin) / cellsize).round() as i64;
+        for r in row - 1..=row + 1 {
+            for c in col - 1..=col + 1 {
+                if r < 0 || c < 0 {
+                    continue;
+                }
+                let Some(ix) = self.ix_opt(r as usize, c as usize) else {
+                    continue;
+                };
+                if self.contains(ix, x, y, cellsize) {
+                    return Some((r as usize, c as usize));
+                }
+            }
+        }
+        None
+    }
+}
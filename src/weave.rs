@@ -0,0 +1,415 @@
+use crate::draw_utils::{self, GtkDrawable};
+use crate::grid::{
+    AbstractCell, AbstractGrid, CellPosition, CompassDirections, CompassGrid, RectangularGrid,
+};
+use crate::gtk::prelude::WidgetExt;
+use crate::play::Move;
+use crate::rectangle::Cell;
+use crate::solve::DijkstraStep;
+use gtk::cairo::Context;
+use gtk::DrawingArea;
+use rand::prelude::*;
+use std::f64::consts::PI;
+
+// Part of the cell width taken by walls, leaving room for bridges.
+const INSET: f64 = 0.3;
+
+/// Rectangular grid where passages can tunnel under perpendicular corridors.
+///
+/// The first `width * height` cells are the usual "over" cells. Linking two over cells that
+/// are two steps apart adds an "under" cell at the end of `cells`, sharing row and column
+/// with the over cell it passes beneath and linked to both ends of the tunnel.
+#[derive(Clone)]
+pub struct WeaveGrid {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<Cell>,
+    /// Under cell below each over cell, if any.
+    pub under: Vec<Option<usize>>,
+}
+
+impl RectangularGrid for WeaveGrid {
+    fn width(&self) -> usize {
+        self.width
+    }
+    fn height(&self) -> usize {
+        self.height
+    }
+}
+
+impl CompassDirections for WeaveGrid {
+    fn north_ix(&self, ix: usize) -> Option<usize> {
+        let row = self.cells[ix].row;
+        let col = self.cells[ix].col;
+        self.ix_opt(row.wrapping_sub(1), col)
+    }
+
+    fn east_ix(&self, ix: usize) -> Option<usize> {
+        let row = self.cells[ix].row;
+        let col = self.cells[ix].col;
+        self.ix_opt(row, col.wrapping_add(1))
+    }
+
+    fn west_ix(&self, ix: usize) -> Option<usize> {
+        let row = self.cells[ix].row;
+        let col = self.cells[ix].col;
+        self.ix_opt(row, col.wrapping_sub(1))
+    }
+
+    fn south_ix(&self, ix: usize) -> Option<usize> {
+        let row = self.cells[ix].row;
+        let col = self.cells[ix].col;
+        self.ix_opt(row.wrapping_add(1), col)
+    }
+}
+
+impl WeaveGrid {
+    pub fn new(rows: usize, cols: usize) -> WeaveGrid {
+        let mut cells = Vec::new();
+        for i in 0..rows {
+            for j in 0..cols {
+                cells.push(Cell::new(i, j));
+            }
+        }
+        WeaveGrid {
+            width: cols,
+            height: rows,
+            cells,
+            under: vec![None; rows * cols],
+        }
+    }
+
+    pub fn is_under(&self, ix: usize) -> bool {
+        ix >= self.width * self.height
+    }
+
+    /// Adjacent over cell in direction 0..4 (north, east, south, west).
+    fn towards(&self, ix: usize, dir: usize) -> Option<usize> {
+        match dir {
+            0 => self.north_ix(ix),
+            1 => self.east_ix(ix),
+            2 => self.south_ix(ix),
+            _ => self.west_ix(ix),
+        }
+    }
+
+    /// Whether the tunnel under cell `ix` runs north-south.
+    fn vertical_tunnel(&self, ix: usize) -> bool {
+        let c = &self.cells[ix];
+        c.links.iter().all(|&n| self.cells[n].col == c.col)
+    }
+
+    /// Whether `ix` has a passage in direction `dir`, over or under the neighbouring cell.
+    pub fn linked_towards(&self, ix: usize, dir: usize) -> bool {
+        let links = &self.cells[ix].links;
+        match self.towards(ix, dir) {
+            Some(n) => links.contains(&n) || self.under[n].is_some_and(|u| links.contains(&u)),
+            None => false,
+        }
+    }
+
+    /// Whether a passage heading in direction `dir` can pass under cell `ix`: `ix` must
+    /// be a straight corridor running across that direction, without a tunnel already.
+    fn can_tunnel(&self, ix: usize, dir: usize) -> bool {
+        if self.under[ix].is_some() || self.towards(ix, dir).is_none() {
+            return false;
+        }
+        let along = |d: usize| self.linked_towards(ix, d);
+        if dir.is_multiple_of(2) {
+            along(1) && along(3) && !along(0) && !along(2)
+        } else {
+            along(0) && along(2) && !along(1) && !along(3)
+        }
+    }
+
+    fn add_under(&mut self, over: usize) -> usize {
+        let ix = self.cells.len();
+        let (row, col) = (self.cells[over].row, self.cells[over].col);
+        self.cells.push(Cell::new(row, col));
+        self.under[over] = Some(ix);
+        ix
+    }
+
+    /// Places up to `attempts` crossings on untouched parts of the grid: a straight
+    /// passage over a random cell with a tunnel under it. Run before a generator that
+    /// keeps existing links, like Kruskal's, to get more crossings than carving alone
+    /// produces.
+    pub fn add_crossings(&mut self, r: &mut rand::rngs::ThreadRng, attempts: usize) {
+        if self.width < 3 || self.height < 3 {
+            return;
+        }
+        for _ in 0..attempts {
+            let row = r.random_range(1..self.height - 1);
+            let col = r.random_range(1..self.width - 1);
+            let ix = self.ix(row, col);
+            let around: Vec<usize> = (0..4).filter_map(|d| self.towards(ix, d)).collect();
+            if self.under[ix].is_some()
+                || !self.cells[ix].links.is_empty()
+                || around.iter().any(|&n| !self.cells[n].links.is_empty())
+            {
+                continue;
+            }
+            let (north, east, south, west) = (around[0], around[1], around[2], around[3]);
+            if r.random_bool(0.5) {
+                self.link(west, ix);
+                self.link(ix, east);
+                self.link(north, south);
+            } else {
+                self.link(north, ix);
+                self.link(ix, south);
+                self.link(west, east);
+            }
+        }
+    }
+}
+
+impl CellPosition for WeaveGrid {
+    fn position(&self, ix: usize) -> (f64, f64) {
+        let c = &self.cells[ix];
+        (c.col as f64 + 0.5, c.row as f64 + 0.5)
+    }
+}
+
+impl CompassGrid<Cell> for WeaveGrid {}
+
+impl AbstractGrid<Cell> for WeaveGrid {
+    /// Over cells see their adjacent cells, except across a tunnel entrance, and the cells
+    /// beyond a corridor they could tunnel under. Under cells are only made by `link`.
+    fn neighbours(&self, ix: usize) -> Vec<usize> {
+        if self.is_under(ix) {
+            return Vec::new();
+        }
+        let mut res = Vec::new();
+        for dir in 0..4 {
+            let Some(n) = self.towards(ix, dir) else {
+                continue;
+            };
+            // No openings into a tunnel from the side, nor out of the sides of a bridge.
+            let across = |c: usize| {
+                self.under[c].is_some_and(|u| self.vertical_tunnel(u) == dir.is_multiple_of(2))
+            };
+            if !across(ix) && !across(n) {
+                res.push(n);
+            }
+            if self.can_tunnel(n, dir) {
+                res.extend(self.towards(n, dir));
+            }
+        }
+        res
+    }
+
+    fn link(&mut self, ix1: usize, ix2: usize) {
+        let (c1, c2) = (&self.cells[ix1], &self.cells[ix2]);
+        let straight = c1.row == c2.row || c1.col == c2.col;
+        let distance = c1.row.abs_diff(c2.row) + c1.col.abs_diff(c2.col);
+        if straight && distance == 2 && !self.is_under(ix1) && !self.is_under(ix2) {
+            let over = self.ix((c1.row + c2.row) / 2, (c1.col + c2.col) / 2);
+            let under = self.add_under(over);
+            self.cells[ix1].link(under);
+            self.cells[under].link(ix1);
+            self.cells[ix2].link(under);
+            self.cells[under].link(ix2);
+        } else {
+            self.cells[ix1].link(ix2);
+            self.cells[ix2].link(ix1);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn cell(&self, ix: usize) -> &Cell {
+        &self.cells[ix]
+    }
+
+    fn cell_mut(&mut self, ix: usize) -> &mut Cell {
+        &mut self.cells[ix]
+    }
+
+    fn as_compass(&mut self) -> Option<&mut dyn CompassGrid<Cell>> {
+        Some(self)
+    }
+}
+
+/// Direction number of a move, as `WeaveGrid::towards` takes them.
+fn move_dir(towards: Move) -> Option<usize> {
+    match towards {
+        Move::North => Some(0),
+        Move::East => Some(1),
+        Move::South => Some(2),
+        Move::West => Some(3),
+        _ => None,
+    }
+}
+
+impl GtkDrawable for WeaveGrid {
+    fn canvas_size(&self, cellsize: f64) -> (f64, f64) {
+        (self.width as f64 * cellsize, self.height as f64 * cellsize)
+    }
+
+    /// Passages under a neighbour lead straight through the tunnel to the cell beyond.
+    fn player_move(&self, ix: usize, towards: Move) -> Option<usize> {
+        if let Some(under) = self.passed_through(ix, towards) {
+            return self.cells[under].links.iter().copied().find(|&m| m != ix);
+        }
+        let n = self.towards(ix, move_dir(towards)?)?;
+        Some(n).filter(|n| self.cells[ix].links.contains(n))
+    }
+
+    fn passed_through(&self, ix: usize, towards: Move) -> Option<usize> {
+        let n = self.towards(ix, move_dir(towards)?)?;
+        let links = &self.cells[ix].links;
+        self.under[n].filter(|u| !links.contains(&n) && links.contains(u))
+    }
+
+    /// Cells under a crossing share the square of the cell above and add nothing.
+    fn cell_path(&self, cr: &Context, ix: usize, cellsize: f64) {
+        if self.is_under(ix) {
+            return;
+        }
+        let c = &self.cells[ix];
+        cr.rectangle(
+            c.col as f64 * cellsize,
+            c.row as f64 * cellsize,
+            cellsize,
+            cellsize,
+        );
+    }
+
+    fn draw_maze(&self, w: &DrawingArea, cr: &Context, cellsize: f64) {
+        self.draw_maze_inset(w, cr, cellsize, INSET);
+    }
+
+    fn draw_maze_inset(&self, w: &DrawingArea, cr: &Context, cellsize: f64, inset: f64) {
+        cr.save().expect("error while saving coords");
+        let scalex = w.allocated_width() as f64 / (self.width as f64 * cellsize);
+        let scaley = w.allocated_height() as f64 / (self.height as f64 * cellsize);
+        cr.scale(scalex, scaley);
+        cr.set_line_width(1.0);
+        let d = cellsize * inset / 2.;
+        for ix in 0..self.len() {
+            let c = self.cell(ix);
+            let x1 = c.col as f64 * cellsize;
+            let y1 = c.row as f64 * cellsize;
+            let (x2, x3, x4) = (x1 + d, x1 + cellsize - d, x1 + cellsize);
+            let (y2, y3, y4) = (y1 + d, y1 + cellsize - d, y1 + cellsize);
+
+            if !self.is_under(ix) {
+                let corners = [(x1, y1), (x4, y1), (x4, y4), (x1, y4)];
+                let open = [0, 1, 2, 3].map(|dir| self.linked_towards(ix, dir));
+                let centre = (x1 + cellsize / 2., y1 + cellsize / 2.);
+                draw_utils::draw_inset_polygon(cr, &corners, centre, &open, inset);
+                continue;
+            }
+
+            // Under cells only show the tunnel mouths on both sides of the bridge.
+            let line = |from: (f64, f64), to: (f64, f64)| {
+                cr.move_to(from.0, from.1);
+                cr.line_to(to.0, to.1);
+            };
+            if self.vertical_tunnel(ix) {
+                line((x2, y1), (x2, y2));
+                line((x3, y1), (x3, y2));
+                line((x2, y3), (x2, y4));
+                line((x3, y3), (x3, y4));
+            } else {
+                line((x1, y2), (x2, y2));
+                line((x1, y3), (x2, y3));
+                line((x3, y2), (x4, y2));
+                line((x3, y3), (x4, y3));
+            }
+            cr.stroke().expect("error while drawing stroke");
+        }
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn draw_pathfind(
+        &self,
+        w: &DrawingArea,
+        cr: &Context,
+        step_state: &DijkstraStep,
+        cellsize: f64,
+    ) {
+        self.draw_pathfind_inset(w, cr, step_state, cellsize, INSET);
+    }
+
+    fn draw_pathfind_inset(
+        &self,
+        w: &DrawingArea,
+        cr: &Context,
+        step_state: &DijkstraStep,
+        cellsize: f64,
+        inset: f64,
+    ) {
+        cr.save().expect("error while saving coords");
+        let scalex = w.allocated_width() as f64 / (self.width as f64 * cellsize);
+        let scaley = w.allocated_height() as f64 / (self.height as f64 * cellsize);
+        cr.scale(scalex, scaley);
+
+        let coords = |ix: usize| {
+            let c = self.cell(ix);
+            (
+                (c.col as f64 + 0.5) * cellsize,
+                (c.row as f64 + 0.5) * cellsize,
+            )
+        };
+
+        let max_idx = step_state.farthest();
+        let max_length = step_state.cell_weights[max_idx].path_length;
+        let path = step_state.path_to(max_idx);
+
+        let inset = cellsize * inset / 2.;
+        for (i, c) in step_state.cell_weights.iter().enumerate() {
+            if c.path_length < 0 || self.is_under(i) {
+                continue;
+            }
+            let intensity = (max_length - c.path_length) as f64 / max_length as f64;
+            cr.set_source_rgb(intensity, 0.5 + intensity / 2., intensity);
+            let (x, y) = coords(i);
+            let half = cellsize / 2. - inset;
+            cr.rectangle(x - half, y - half, 2. * half, 2. * half);
+            cr.fill().expect("error while drawing stroke");
+        }
+
+        cr.set_line_width(1.0);
+        cr.set_source_rgb(0., 0., 0.);
+        for ix in [path.first(), path.last()].into_iter().flatten() {
+            let (x, y) = coords(*ix);
+            cr.new_sub_path();
+            cr.arc(x, y, cellsize / 2. - inset, 0., 2. * PI);
+            cr.stroke().expect("error while drawing stroke");
+        }
+
+        // Steps into or out of a tunnel are dashed.
+        cr.set_source_rgb(1., 0., 0.);
+        cr.set_line_width(2.0);
+        for pair in path.windows(2) {
+            let hidden = self.is_under(pair[0]) || self.is_under(pair[1]);
+            if hidden {
+                cr.set_dash(&[cellsize / 5.], 0.);
+            }
+            let (x1, y1) = coords(pair[0]);
+            let (x2, y2) = coords(pair[1]);
+            cr.move_to(x1, y1);
+            cr.line_to(x2, y2);
+            cr.stroke().expect("error while drawing stroke");
+            if hidden {
+                cr.set_dash(&[], 0.);
+            }
+        }
+        cr.restore().expect("error while restoring coords");
+    }
+
+    fn cell_at(&self, w: &DrawingArea, x: f64, y: f64, cellsize: f64) -> Option<(usize, usize)> {
+        let scalex = w.allocated_width() as f64 / (self.width as f64 * cellsize);
+        let scaley = w.allocated_height() as f64 / (self.height as f64 * cellsize);
+        let col = (x / scalex / cellsize).floor();
+        let row = (y / scaley / cellsize).floor();
+        if col < 0. || row < 0. {
+            return None;
+        }
+        let ix = self.ix_opt(row as usize, col as usize)?;
+        Some((self.cells[ix].row, self.cells[ix].col))
+    }
+}